@@ -18,8 +18,16 @@ pub fn action_builddir(dir: &Option<PathBuf>, rua_paths: &RuaPaths, offline: boo
 	let dir_str = dir
 		.to_str()
 		.unwrap_or_else(|| panic!("{}:{} Cannot parse CLI target directory", file!(), line!()));
-	wrapped::build_directory(dir_str, rua_paths, offline, force);
+	let current_srcinfo =
+		wrapped::generate_srcinfo(dir_str, rua_paths).expect("Failed to obtain SRCINFO");
+	let log = wrapped::new_build_log(
+		&current_srcinfo.base.pkgbase,
+		&current_srcinfo.version(),
+		rua_paths,
+	);
+	wrapped::build_directory(dir_str, rua_paths, offline, force, &log);
 
+	// Generated again, because `pkgver()` might have updated the version
 	let srcinfo = wrapped::generate_srcinfo(dir_str, rua_paths).expect("Failed to obtain SRCINFO");
 	let ver = srcinfo.version();
 	let packages = srcinfo.pkgs.iter().map(|package| {
@@ -39,9 +47,9 @@ pub fn action_builddir(dir: &Option<PathBuf>, rua_paths: &RuaPaths, offline: boo
 
 	for (_, file) in &packages {
 		let file_str = file.to_str().expect("Builddir target has unvalid UTF-8");
-		tar_check::tar_check(file, file_str).ok();
+		tar_check::tar_check(file, file_str, Some(&srcinfo), None).ok();
 	}
 	eprintln!("Package built and checked.");
 
-	pacman::ensure_aur_packages_installed(packages, false);
+	pacman::ensure_aur_packages_installed(packages, false, None);
 }
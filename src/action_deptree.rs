@@ -0,0 +1,304 @@
+use crate::alpm_wrapper::new_alpm_wrapper;
+use crate::aur_rpc_utils;
+use crate::aur_wrapper::new_aur_wrapper;
+use crate::cli_args::DeptreeFormat;
+use crate::dependency_graph::DependencyGraph;
+use crate::dependency_graph::DependencyKind;
+use crate::dependency_graph::Edge;
+use crate::dependency_graph::NodeKind;
+use indexmap::IndexSet;
+use itertools::Itertools;
+use serde::Serialize;
+
+/// What needs to be done about a package in the tree
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Status {
+	/// Will be built from AUR
+	Aur,
+	/// Will be installed from pacman repositories
+	Repo,
+	/// Already installed
+	Installed,
+	/// Needs to be built from AUR, but is not there
+	NotFound,
+}
+
+impl Status {
+	fn of(graph: &DependencyGraph, name: &str) -> Status {
+		let kind = graph
+			.node_kind(name)
+			.expect("Internal error: package is not in the dependency graph");
+		match kind {
+			NodeKind::Aur if graph.pkgbase(name).is_none() => Status::NotFound,
+			NodeKind::Aur => Status::Aur,
+			NodeKind::Repo => Status::Repo,
+			NodeKind::Installed => Status::Installed,
+		}
+	}
+
+	fn label(self) -> &'static str {
+		match self {
+			Status::Aur => "aur",
+			Status::Repo => "repo",
+			Status::Installed => "installed",
+			Status::NotFound => "not-found",
+		}
+	}
+
+	fn dot_attributes(self) -> &'static str {
+		match self {
+			Status::Aur => "shape=box",
+			Status::Repo => "color=darkgreen",
+			Status::Installed => "color=gray, fontcolor=gray",
+			Status::NotFound => "color=red, fontcolor=red",
+		}
+	}
+}
+
+pub fn action_deptree(targets: &[String], format: DeptreeFormat, reverse: Option<&str>) {
+	let alpm = new_alpm_wrapper();
+	let aur = new_aur_wrapper();
+	let (_, _, graph) =
+		aur_rpc_utils::recursive_info(targets, &*alpm, &*aur, None).unwrap_or_else(|err| {
+			panic!("Failed to fetch info from AUR, {}", err);
+		});
+	if let Some(package) = reverse {
+		if graph.node_kind(package).is_none() {
+			eprintln!("{} is not a dependency of {}", package, targets.join(" "));
+			std::process::exit(1)
+		}
+	}
+	let output = match (format, reverse) {
+		(DeptreeFormat::plain, None) => {
+			let roots = graph.roots().iter().map(String::as_str).collect_vec();
+			plain_tree(&graph, &roots, false)
+		}
+		(DeptreeFormat::plain, Some(package)) => plain_tree(&graph, &[package], true),
+		(DeptreeFormat::dot, _) => dot(&graph, &shown_packages(&graph, reverse)),
+		(DeptreeFormat::json, _) => json(&graph, &shown_packages(&graph, reverse)),
+	};
+	print!("{}", output);
+}
+
+/// All packages of the graph, or only the ones (transitively) requiring `reverse`
+fn shown_packages<'a>(graph: &'a DependencyGraph, reverse: Option<&'a str>) -> IndexSet<&'a str> {
+	let package = match reverse {
+		None => return graph.nodes().map(|(name, _kind)| name).collect(),
+		Some(package) => package,
+	};
+	let mut result = IndexSet::new();
+	result.insert(package);
+	let mut index = 0;
+	while let Some(name) = result.get_index(index).copied() {
+		result.extend(graph.dependents(name).map(|edge| edge.from.as_str()));
+		index += 1;
+	}
+	result
+}
+
+fn shown_edges<'a>(
+	graph: &'a DependencyGraph,
+	shown: &'a IndexSet<&str>,
+) -> impl Iterator<Item = &'a Edge> {
+	let edges = graph.edges().iter();
+	edges.filter(|edge| shown.contains(edge.from.as_str()) && shown.contains(edge.to.as_str()))
+}
+
+/// Renders the tree like `pactree` does. With `reverse`, children are the packages
+/// depending on their parent instead of its dependencies.
+fn plain_tree(graph: &DependencyGraph, roots: &[&str], reverse: bool) -> String {
+	let mut output = String::new();
+	for root in roots {
+		output.push_str(&format!("{} [{}]\n", root, Status::of(graph, root).label()));
+		plain_children(graph, root, reverse, &mut vec![root], "", &mut output);
+	}
+	output
+}
+
+fn plain_children<'a>(
+	graph: &'a DependencyGraph,
+	name: &'a str,
+	reverse: bool,
+	path: &mut Vec<&'a str>,
+	prefix: &str,
+	output: &mut String,
+) {
+	let children = if reverse {
+		let edges = graph.dependents(name);
+		edges
+			.map(|edge| (edge.from.as_str(), edge.kind))
+			.collect_vec()
+	} else {
+		let edges = graph.dependencies(name);
+		edges
+			.map(|edge| (edge.to.as_str(), edge.kind))
+			.collect_vec()
+	};
+	for (index, (child, kind)) in children.iter().enumerate() {
+		let is_last = index + 1 == children.len();
+		output.push_str(prefix);
+		output.push_str(if is_last { "└── " } else { "├── " });
+		output.push_str(&format!("{} [{}]", child, Status::of(graph, child).label()));
+		if *kind != DependencyKind::Depends {
+			output.push_str(&format!(" ({})", kind));
+		}
+		if path.contains(child) {
+			output.push_str(" (cycle)\n");
+			continue;
+		}
+		output.push('\n');
+		path.push(child);
+		let prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+		plain_children(graph, child, reverse, path, &prefix, output);
+		path.pop();
+	}
+}
+
+fn dot(graph: &DependencyGraph, shown: &IndexSet<&str>) -> String {
+	let mut output = String::from("digraph deptree {\n");
+	for name in shown {
+		let attributes = Status::of(graph, name).dot_attributes();
+		output.push_str(&format!("\t\"{}\" [{}];\n", name, attributes));
+	}
+	for edge in shown_edges(graph, shown) {
+		let style = match edge.kind {
+			DependencyKind::Depends => "solid",
+			DependencyKind::MakeDepends => "dashed",
+			DependencyKind::CheckDepends => "dotted",
+		};
+		output.push_str(&format!(
+			"\t\"{}\" -> \"{}\" [style={}];\n",
+			edge.from, edge.to, style
+		));
+	}
+	output.push_str("}\n");
+	output
+}
+
+#[derive(Serialize)]
+struct JsonTree<'a> {
+	roots: Vec<&'a str>,
+	nodes: Vec<JsonNode<'a>>,
+	edges: Vec<JsonEdge<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonNode<'a> {
+	name: &'a str,
+	status: Status,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pkgbase: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+	from: &'a str,
+	to: &'a str,
+	kind: String,
+}
+
+fn json(graph: &DependencyGraph, shown: &IndexSet<&str>) -> String {
+	let roots = graph.roots().iter().map(String::as_str);
+	let nodes = shown.iter().map(|name| JsonNode {
+		name,
+		status: Status::of(graph, name),
+		pkgbase: graph.pkgbase(name),
+	});
+	let edges = shown_edges(graph, shown).map(|edge| JsonEdge {
+		from: &edge.from,
+		to: &edge.to,
+		kind: edge.kind.to_string(),
+	});
+	let tree = JsonTree {
+		roots: roots.filter(|root| shown.contains(root)).collect(),
+		nodes: nodes.collect(),
+		edges: edges.collect(),
+	};
+	let mut output =
+		serde_json::to_string_pretty(&tree).expect("Failed to serialize dependency tree to JSON");
+	output.push('\n');
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::action_deptree::*;
+	use crate::fake_wrappers::FakeAlpm;
+	use crate::fake_wrappers::FakeAur;
+
+	fn resolve(targets: &[&str]) -> DependencyGraph {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let aur = FakeAur::from_fixture("aur.json");
+		let targets = targets.iter().map(|t| t.to_string()).collect_vec();
+		let (_, _, graph) = aur_rpc_utils::recursive_info(&targets, &alpm, &aur, None).unwrap();
+		graph
+	}
+
+	#[test]
+	fn test_plain_tree() {
+		let graph = resolve(&["app", "needs-missing"]);
+		let expected = "\
+app [aur]
+├── cmake [repo] (makedepends)
+├── liba [aur]
+│   ├── libb [aur]
+│   │   └── glibc [installed]
+│   ├── sh [installed]
+│   └── libtest-helper [aur] (checkdepends)
+├── glibc [installed]
+└── python3 [repo]
+needs-missing [aur]
+└── not-anywhere [not-found]
+";
+		assert_eq!(
+			plain_tree(&graph, &["app", "needs-missing"], false),
+			expected
+		);
+	}
+
+	#[test]
+	fn test_plain_tree_reverse_with_cycle() {
+		let graph = resolve(&["cycle-a", "split-two"]);
+		let expected = "\
+cycle-a [aur]
+└── cycle-b [aur] (makedepends)
+    └── cycle-a [aur] (cycle)
+";
+		assert_eq!(plain_tree(&graph, &["cycle-a"], true), expected);
+	}
+
+	#[test]
+	fn test_json_reverse() {
+		let graph = resolve(&["app", "split-two"]);
+		let shown = shown_packages(&graph, Some("libb"));
+		assert_eq!(
+			shown.iter().copied().sorted().collect_vec(),
+			["app", "liba", "libb", "split-one", "split-two"]
+		);
+		let json: serde_json::Value = serde_json::from_str(&json(&graph, &shown)).unwrap();
+		assert_eq!(json["roots"], serde_json::json!(["app", "split-two"]));
+		assert_eq!(
+			json["nodes"][0],
+			serde_json::json!({"name": "libb", "status": "aur", "pkgbase": "libb"})
+		);
+		let edges = json["edges"].as_array().unwrap();
+		assert_eq!(edges.len(), 4);
+		assert!(edges.contains(&serde_json::json!(
+			{"from": "split-one", "to": "libb", "kind": "depends"}
+		)));
+	}
+
+	#[test]
+	fn test_dot() {
+		let graph = resolve(&["needs-virtual"]);
+		let expected = "\
+digraph deptree {
+	\"needs-virtual\" [shape=box];
+	\"runtime-bin\" [shape=box];
+	\"needs-virtual\" -> \"runtime-bin\" [style=solid];
+}
+";
+		assert_eq!(dot(&graph, &shown_packages(&graph, None)), expected);
+	}
+}
@@ -1,36 +1,57 @@
 use crate::alpm_wrapper::new_alpm_wrapper;
 use crate::aur_rpc_utils;
+use crate::aur_wrapper::new_aur_wrapper;
+use crate::build_log::BuildLog;
+use crate::dependency_graph::DependencyGraph;
+use crate::git_utils;
+use crate::local_repo::LocalRepo;
+use crate::noconfirm::NoConfirm;
 use crate::pacman;
 use crate::reviewing;
 use crate::rua_paths::RuaPaths;
 use crate::tar_check;
 use crate::terminal_util;
 use crate::wrapped;
+use colored::Colorize;
 use fs_extra::dir::CopyOptions;
-use indexmap::IndexMap;
 use indexmap::IndexSet;
 use itertools::Itertools;
 use log::debug;
 use log::trace;
-use std::collections::HashSet;
+use log::warn;
+use prettytable::format::FormatBuilder;
+use prettytable::row;
+use prettytable::Table;
+use srcinfo::Srcinfo;
 use std::fs;
 use std::fs::ReadDir;
+use std::io;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Instant;
 
-pub fn install(targets: &[String], rua_paths: &RuaPaths, is_offline: bool, asdeps: bool) {
+/// How packages are built and installed, see `crate::cli_args::Action::Install`
+pub struct InstallOptions {
+	pub offline: bool,
+	pub asdeps: bool,
+	/// Maximum number of packages built at the same time
+	pub jobs: usize,
+	pub noconfirm: Option<NoConfirm>,
+	pub local_repo: Option<LocalRepo>,
+}
+
+pub fn install(targets: &[String], rua_paths: &RuaPaths, options: &InstallOptions) {
+	let noconfirm = options.noconfirm.as_ref();
 	let alpm = new_alpm_wrapper();
-	let (split_to_raur, pacman_deps, split_to_depth) =
-		aur_rpc_utils::recursive_info(targets, &*alpm).unwrap_or_else(|err| {
+	let aur = new_aur_wrapper();
+	let (_split_to_raur, pacman_deps, graph) =
+		aur_rpc_utils::recursive_info(targets, &*alpm, &*aur, noconfirm).unwrap_or_else(|err| {
 			panic!("Failed to fetch info from AUR, {}", err);
 		});
-	let split_to_pkgbase: IndexMap<String, String> = split_to_raur
-		.iter()
-		.map(|(split, raur)| (split.to_string(), raur.package_base.to_string()))
-		.collect();
-	let not_found = split_to_depth
-		.keys()
-		.filter(|pkg| !split_to_raur.contains_key(*pkg))
-		.collect_vec();
+	let not_found = graph.not_found();
 	if !not_found.is_empty() {
 		eprintln!(
 			"Need to install packages: {:?}, but they are not found on AUR.",
@@ -38,23 +59,22 @@ pub fn install(targets: &[String], rua_paths: &RuaPaths, is_offline: bool, asdep
 		);
 		std::process::exit(1)
 	}
+	let build_order = graph.build_order().unwrap_or_else(|cycle| {
+		eprintln!("AUR packages depend on each other in a cycle, cannot find a build order:");
+		eprintln!("  {}", cycle);
+		std::process::exit(1)
+	});
 
-	show_install_summary(&pacman_deps, &split_to_depth);
-	for pkgbase in split_to_pkgbase.values().collect::<HashSet<_>>() {
+	show_install_summary(&pacman_deps, &graph, &build_order, options);
+	for pkgbase in build_order.iter().flatten() {
 		let dir = rua_paths.review_dir(pkgbase);
 		fs::create_dir_all(&dir).unwrap_or_else(|err| {
 			panic!("Failed to create repository dir for {}, {}", pkgbase, err)
 		});
-		reviewing::review_repo(&dir, pkgbase, rua_paths);
+		reviewing::review_repo(&dir, pkgbase, rua_paths, noconfirm);
 	}
-	pacman::ensure_pacman_packages_installed(pacman_deps);
-	install_all(
-		rua_paths,
-		split_to_depth,
-		split_to_pkgbase,
-		is_offline,
-		asdeps,
-	);
+	pacman::ensure_pacman_packages_installed(pacman_deps, noconfirm);
+	install_all(rua_paths, &graph, &build_order, options);
 	for target in targets {
 		// Delete temp directories after successful build+install
 		if let Err(err) = rm_rf::remove(rua_paths.build_dir(target)) {
@@ -68,7 +88,17 @@ pub fn install(targets: &[String], rua_paths: &RuaPaths, is_offline: bool, asdep
 	}
 }
 
-fn show_install_summary(pacman_deps: &IndexSet<String>, aur_packages: &IndexMap<String, i32>) {
+fn show_install_summary(
+	pacman_deps: &IndexSet<String>,
+	graph: &DependencyGraph,
+	build_order: &[Vec<String>],
+	options: &InstallOptions,
+) {
+	let aur_packages = build_order
+		.iter()
+		.flatten()
+		.flat_map(|pkgbase| graph.splits(pkgbase))
+		.collect_vec();
 	if pacman_deps.len() + aur_packages.len() == 1 {
 		return;
 	}
@@ -79,16 +109,28 @@ fn show_install_summary(pacman_deps: &IndexSet<String>, aur_packages: &IndexMap<
 			pacman_deps.iter().map(|s| format!("  {}", s)).join("\n")
 		);
 	};
-	eprintln!("\nAnd the following AUR packages will need to be built and installed:");
-	let mut aur_packages = aur_packages.iter().collect::<Vec<_>>();
-	aur_packages.sort_by_key(|pair| -*pair.1);
-	for (aur, dep) in &aur_packages {
-		debug!("depth {}: {}", dep, aur);
+	match &options.local_repo {
+		None => eprintln!("\nAnd the following AUR packages will need to be built and installed:"),
+		Some(repo) => eprintln!(
+			"\nAnd the following AUR packages will need to be built and added to {:?}:",
+			repo.db_path()
+		),
+	}
+	for (index, stage) in build_order.iter().enumerate() {
+		debug!("build stage {}: {:?}", index + 1, stage);
+	}
+	for aur in &aur_packages {
+		let deps = graph.dependencies(aur);
+		let deps = deps.map(|edge| format!("{} ({})", edge.to, edge.kind));
+		debug!("{} needs: {}", aur, deps.format(", "));
 	}
 	eprintln!(
 		"{}\n",
-		aur_packages.iter().map(|s| format!("  {}", s.0)).join("\n")
+		aur_packages.iter().map(|s| format!("  {}", s)).join("\n")
 	);
+	if options.noconfirm.is_some() {
+		return;
+	}
 	loop {
 		eprint!("Proceed? [O]=ok, Ctrl-C=abort. ");
 		let string = terminal_util::read_line_lowercase();
@@ -100,97 +142,233 @@ fn show_install_summary(pacman_deps: &IndexSet<String>, aur_packages: &IndexMap<
 
 fn install_all(
 	rua_paths: &RuaPaths,
-	split_to_depth: IndexMap<String, i32>,
-	split_to_pkgbase: IndexMap<String, String>,
-	offline: bool,
-	asdeps: bool,
+	graph: &DependencyGraph,
+	build_order: &[Vec<String>],
+	options: &InstallOptions,
 ) {
-	let archive_whitelist = split_to_depth
-		.iter()
-		.map(|(split, _depth)| split.as_str())
-		.collect::<IndexSet<_>>();
+	let noconfirm = options.noconfirm.as_ref();
+	let archive_whitelist = graph.aur_splits().collect::<IndexSet<_>>();
 	trace!("All expected split packages: {:?}", archive_whitelist);
-	// get a list of (pkgbase, depth)
-	let packages = split_to_pkgbase.iter().map(|(split, pkgbase)| {
-		let depth = split_to_depth
-			.get(split)
-			.expect("Internal error: split package doesn't have recursive depth");
-		(pkgbase.to_string(), *depth, split.to_string())
-	});
-	// sort pairs in descending depth order
-	let packages = packages.sorted_by_key(|(_pkgbase, depth, _split)| -depth);
-	// Note that a pkgbase can appear at multiple depths because
-	// multiple split pkgnames can be at multiple depths.
-	// In this case, we only take the first occurrence of pkgbase,
-	// which would be the maximum depth because of sort order.
-	// We only take one occurrence because we want the package to only be built once.
-	let packages: Vec<(String, i32, String)> = packages
-		.unique_by(|(pkgbase, _depth, _split)| pkgbase.to_string())
-		.collect::<Vec<_>>();
-	// once we have a collection of pkgname-s and their depth, proceed straightforwardly.
-	for (depth, packages) in &packages.iter().chunk_by(|(_pkgbase, depth, _split)| *depth) {
-		let packages = packages.collect::<Vec<&(String, i32, String)>>();
-		for (pkgbase, _depth, _split) in &packages {
-			let review_dir = rua_paths.review_dir(pkgbase);
-			let build_dir = rua_paths.build_dir(pkgbase);
-			rm_rf::ensure_removed(&build_dir).unwrap_or_else(|err| {
-				panic!("Failed to remove old build dir {:?}, {}", &build_dir, err)
-			});
-			std::fs::create_dir_all(&build_dir).unwrap_or_else(|err| {
-				panic!("Failed to create build dir {:?}, {}", &build_dir, err)
-			});
-			fs_extra::copy_items(
-				&[&review_dir],
-				&rua_paths.global_build_dir,
-				&CopyOptions::new(),
-			)
-			.unwrap_or_else(|err| {
-				panic!(
-					"failed to copy reviewed dir {:?} to build dir {:?}, error is {}",
-					&review_dir, rua_paths.global_build_dir, err
-				)
-			});
-			{
-				let dir_to_remove = build_dir.join(".git");
-				rm_rf::ensure_removed(build_dir.join(".git"))
-					.unwrap_or_else(|err| panic!("Failed to remove {:?}, {}", dir_to_remove, err));
+	// Each stage only depends on packages from previous stages, proceed straightforwardly.
+	for packages in build_order {
+		for pkgbase in packages {
+			prepare_build_dir(pkgbase, rua_paths);
+		}
+		if options.jobs > 1 && packages.len() > 1 {
+			build_in_parallel(packages, rua_paths, options);
+		} else {
+			for pkgbase in packages {
+				let log = build_log(pkgbase, rua_paths);
+				wrapped::build_directory(
+					rua_paths
+						.build_dir(pkgbase)
+						.to_str()
+						.expect("Non-UTF8 directory name"),
+					rua_paths,
+					options.offline,
+					false,
+					&log,
+				);
 			}
-			wrapped::build_directory(
-				build_dir.to_str().expect("Non-UTF8 directory name"),
-				rua_paths,
-				offline,
-				false,
-			);
 		}
-		for (pkgbase, _depth, _split) in &packages {
-			check_tars_and_move(pkgbase, rua_paths, &archive_whitelist);
+		for pkgbase in packages {
+			check_tars_and_move(pkgbase, rua_paths, &archive_whitelist, noconfirm);
+			git_utils::mark_built(&rua_paths.review_dir(pkgbase), rua_paths);
+		}
+		if let Some(local_repo) = &options.local_repo {
+			add_to_local_repo(rua_paths, graph, packages, local_repo, noconfirm);
+			continue;
 		}
-		// This relation between split_name and the archive file is not actually correct here.
-		// Instead, all archive files of some group will be bound to one split name only here.
-		// This is probably still good enough for install verification though --
-		// and we only use this relation for this purpose. Feel free to improve, if you want...
-		let mut files_to_install: Vec<(String, PathBuf)> = Vec::new();
-		for (pkgbase, _depth, split) in &packages {
-			let checked_tars = rua_paths.checked_tars_dir(pkgbase);
-			let read_dir_iterator = fs::read_dir(checked_tars).unwrap_or_else(|e| {
-				panic!(
-					"Failed to read 'checked_tars' directory for {}, {}",
-					pkgbase, e
-				)
+		// Packages requested by the user are installed explicitly, the rest as dependencies
+		let (explicit, dependencies): (Vec<&String>, Vec<&String>) =
+			packages.iter().partition(|pkgbase| {
+				!options.asdeps
+					&& graph
+						.splits(pkgbase)
+						.iter()
+						.any(|split| graph.roots().contains(*split))
 			});
-
-			for file in read_dir_iterator {
-				files_to_install.push((
-					split.to_string(),
-					file.expect("Failed to access checked_tars dir").path(),
-				));
+		for (pkgbases, is_dependency) in [(dependencies, true), (explicit, false)] {
+			if pkgbases.is_empty() {
+				continue;
 			}
+			let files_to_install = checked_tars(rua_paths, graph, &pkgbases);
+			pacman::ensure_aur_packages_installed(files_to_install, is_dependency, noconfirm);
 		}
-		pacman::ensure_aur_packages_installed(files_to_install, asdeps || depth > 0);
 	}
 }
 
-pub fn check_tars_and_move(name: &str, rua_paths: &RuaPaths, archive_whitelist: &IndexSet<&str>) {
+/// Copies the reviewed repository to a clean build directory
+fn prepare_build_dir(pkgbase: &str, rua_paths: &RuaPaths) {
+	let review_dir = rua_paths.review_dir(pkgbase);
+	let build_dir = rua_paths.build_dir(pkgbase);
+	rm_rf::ensure_removed(&build_dir)
+		.unwrap_or_else(|err| panic!("Failed to remove old build dir {:?}, {}", &build_dir, err));
+	std::fs::create_dir_all(&build_dir)
+		.unwrap_or_else(|err| panic!("Failed to create build dir {:?}, {}", &build_dir, err));
+	fs_extra::copy_items(
+		&[&review_dir],
+		&rua_paths.global_build_dir,
+		&CopyOptions::new(),
+	)
+	.unwrap_or_else(|err| {
+		panic!(
+			"failed to copy reviewed dir {:?} to build dir {:?}, error is {}",
+			&review_dir, rua_paths.global_build_dir, err
+		)
+	});
+	{
+		let dir_to_remove = build_dir.join(".git");
+		rm_rf::ensure_removed(build_dir.join(".git"))
+			.unwrap_or_else(|err| panic!("Failed to remove {:?}, {}", dir_to_remove, err));
+	}
+}
+
+/// Creates the build log, named after the version in the reviewed .SRCINFO
+fn build_log(pkgbase: &str, rua_paths: &RuaPaths) -> BuildLog {
+	let srcinfo_path = rua_paths.review_dir(pkgbase).join(".SRCINFO");
+	let version = match Srcinfo::from_path(&srcinfo_path) {
+		Ok(srcinfo) => srcinfo.version(),
+		Err(err) => {
+			warn!("Failed to get the version for the build log, {}", err);
+			"unknown".to_string()
+		}
+	};
+	wrapped::new_build_log(pkgbase, &version, rua_paths)
+}
+
+/// Builds packages of one stage concurrently, each in its own jail,
+/// with the output of each build going to its own log file.
+/// Exits after showing the results if any of the builds failed.
+fn build_in_parallel(pkgbases: &[String], rua_paths: &RuaPaths, options: &InstallOptions) {
+	let next_package = AtomicUsize::new(0);
+	let build_next = || {
+		let mut results = Vec::new();
+		while let Some(pkgbase) = pkgbases.get(next_package.fetch_add(1, Ordering::SeqCst)) {
+			let dir = rua_paths.build_dir(pkgbase);
+			let dir = dir.to_str().expect("Non-UTF8 directory name");
+			let log = build_log(pkgbase, rua_paths);
+			eprintln!("Building {}, logging to {:?}", pkgbase, log.path);
+			let started = Instant::now();
+			let result =
+				wrapped::try_build_directory(dir, rua_paths, options.offline, false, &log, false);
+			results.push((pkgbase, result, started.elapsed(), log.path));
+		}
+		results
+	};
+	let workers = options.jobs.min(pkgbases.len());
+	let results = thread::scope(|scope| {
+		let workers = (0..workers).map(|_| scope.spawn(build_next)).collect_vec();
+		workers
+			.into_iter()
+			.flat_map(|worker| worker.join().expect("Build thread panicked"))
+			.collect_vec()
+	});
+
+	let mut table = Table::new();
+	table.set_titles(row![
+		"Package".underline(),
+		"Result".underline(),
+		"Time".underline(),
+		"Log".underline()
+	]);
+	for (pkgbase, result, elapsed, log_path) in &results {
+		let status = match result {
+			Ok(()) => "built".green(),
+			Err(failure) => format!("failed, exit code {}", failure.exit_code).red(),
+		};
+		let elapsed = format!("{}m{:02}s", elapsed.as_secs() / 60, elapsed.as_secs() % 60);
+		table.add_row(row![pkgbase.yellow(), status, elapsed, log_path.display()]);
+	}
+	table.set_format(FormatBuilder::new().padding(0, 1).build());
+	table.printstd();
+	eprintln!();
+	if results.iter().any(|(_, result, _, _)| result.is_err()) {
+		eprintln!("Some packages failed to build, see the logs above.");
+		std::process::exit(1)
+	}
+}
+
+/// Moves the checked archives into the local repository instead of installing them.
+/// Packages that later stages depend on are still installed as dependencies,
+/// because the later stages are built on this system.
+fn add_to_local_repo(
+	rua_paths: &RuaPaths,
+	graph: &DependencyGraph,
+	pkgbases: &[String],
+	local_repo: &LocalRepo,
+	noconfirm: Option<&NoConfirm>,
+) {
+	let pkgbases = pkgbases.iter().collect_vec();
+	let mut files_in_repo: Vec<(String, PathBuf)> = Vec::new();
+	for (split, file) in checked_tars(rua_paths, graph, &pkgbases) {
+		let file_name = file
+			.file_name()
+			.unwrap_or_else(|| panic!("Package file {:?} has no name", file));
+		let dst = local_repo.dir().join(file_name);
+		move_file(&file, &dst).unwrap_or_else(|e| {
+			panic!(
+				"Failed to move {:?} to local repository {:?}, {}",
+				file,
+				local_repo.dir(),
+				e
+			)
+		});
+		files_in_repo.push((split, dst));
+	}
+	local_repo.add(
+		&files_in_repo
+			.iter()
+			.map(|(_split, file)| file.to_path_buf())
+			.collect_vec(),
+	);
+	files_in_repo.retain(|(split, _file)| {
+		let pkgbase = graph
+			.pkgbase(split)
+			.expect("Internal error: AUR package without pkgbase");
+		graph.is_needed_by_others(pkgbase)
+	});
+	if !files_in_repo.is_empty() {
+		pacman::ensure_aur_packages_installed(files_in_repo, true, noconfirm);
+	}
+}
+
+fn checked_tars(
+	rua_paths: &RuaPaths,
+	graph: &DependencyGraph,
+	pkgbases: &[&String],
+) -> Vec<(String, PathBuf)> {
+	// This relation between split_name and the archive file is not actually correct here.
+	// Instead, all archive files of some group will be bound to one split name only here.
+	// This is probably still good enough for install verification though --
+	// and we only use this relation for this purpose. Feel free to improve, if you want...
+	let mut files_to_install: Vec<(String, PathBuf)> = Vec::new();
+	for pkgbase in pkgbases {
+		let split = graph.splits(pkgbase)[0];
+		let checked_tars = rua_paths.checked_tars_dir(pkgbase);
+		let read_dir_iterator = fs::read_dir(checked_tars).unwrap_or_else(|e| {
+			panic!(
+				"Failed to read 'checked_tars' directory for {}, {}",
+				pkgbase, e
+			)
+		});
+
+		for file in read_dir_iterator {
+			files_to_install.push((
+				split.to_string(),
+				file.expect("Failed to access checked_tars dir").path(),
+			));
+		}
+	}
+	files_to_install
+}
+
+pub fn check_tars_and_move(
+	name: &str,
+	rua_paths: &RuaPaths,
+	archive_whitelist: &IndexSet<&str>,
+	noconfirm: Option<&NoConfirm>,
+) {
 	debug!("checking tars and moving for package {}", name);
 	let build_dir = rua_paths.build_dir(name);
 	let dir_items: ReadDir = build_dir.read_dir().unwrap_or_else(|err| {
@@ -218,8 +396,9 @@ pub fn check_tars_and_move(name: &str, rua_paths: &RuaPaths, archive_whitelist:
 	dir_items
 		.retain(|(_, name)| archive_whitelist.contains(&name[..name.len() - common_suffix_length]));
 	trace!("Files filtered for tar checking: {:?}", &dir_items);
+	let srcinfo = Srcinfo::from_path(rua_paths.review_dir(name).join(".SRCINFO")).ok();
 	for (file, file_name) in dir_items.iter() {
-		tar_check::tar_check_unwrap(&file.path(), file_name);
+		tar_check::tar_check_unwrap(&file.path(), file_name, srcinfo.as_ref(), noconfirm);
 	}
 	debug!("all package (tar) files checked, moving them");
 	let checked_tars_dir = rua_paths.checked_tars_dir(name);
@@ -240,35 +419,67 @@ pub fn check_tars_and_move(name: &str, rua_paths: &RuaPaths, archive_whitelist:
 		let src = &file.path();
 		let dst = &checked_tars_dir.join(file_name);
 
-		fs::rename(src, dst)
-			.or_else(|err| {
-				// We want to make the "move" operation as fast as possible.
-				// First we attempt to do it in one single system call, "rename".
-				//
-				// That might fail if the XDG directories ~/.cache/rua and /.local/share/
-				// live on different devices (or your XDG directories do, if you defined them).
-				// In that, and only in that case, we try to copy the file and remove upon completion.
+		move_file(src, dst).unwrap_or_else(|e| {
+			panic!(
+				"Failed to move {:?} (build artifact) to {:?}, {}",
+				&file, &checked_tars_dir, e,
+			)
+		});
+	}
+}
+
+/// Moves a file, copying it if the destination is on a different device
+fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
+	fs::rename(src, dst).or_else(|err| {
+		// We want to make the "move" operation as fast as possible.
+		// First we attempt to do it in one single system call, "rename".
+		//
+		// That might fail if the XDG directories ~/.cache/rua and /.local/share/
+		// live on different devices (or your XDG directories do, if you defined them).
+		// In that, and only in that case, we try to copy the file and remove upon completion.
 
-				// References:
-				// RUA pull request: https://github.com/vn971/rua/pull/109
-				// coreutils copying: https://github.com/coreutils/coreutils/blob/9b4bb9d28a6a5f84c407f795d518726fd7902121/src/copy.c#L2466
+		// References:
+		// RUA pull request: https://github.com/vn971/rua/pull/109
+		// coreutils copying: https://github.com/coreutils/coreutils/blob/9b4bb9d28a6a5f84c407f795d518726fd7902121/src/copy.c#L2466
 
-				if err.raw_os_error() != Some(libc::EXDEV) {
-					// EXDEV (invalid cross-device link) gets aggregated into io::ErrorKind::Other
-					return Err(err);
-				}
+		if err.raw_os_error() != Some(libc::EXDEV) {
+			// EXDEV (invalid cross-device link) gets aggregated into io::ErrorKind::Other
+			return Err(err);
+		}
 
-				// can't move across disks, copy & delete instead
-				fs::copy(src, dst)?;
-				let _ = fs::remove_file(src);
+		// can't move across disks, copy & delete instead
+		fs::copy(src, dst)?;
+		let _ = fs::remove_file(src);
 
-				Ok(())
-			})
-			.unwrap_or_else(|e| {
-				panic!(
-					"Failed to move {:?} (build artifact) to {:?}, {}",
-					&file, &checked_tars_dir, e,
-				)
-			});
+		Ok(())
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::aur_rpc_utils;
+	use crate::fake_wrappers::FakeAlpm;
+	use crate::fake_wrappers::FakeAur;
+	use itertools::Itertools;
+
+	#[test]
+	fn test_build_order() {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let aur = FakeAur::from_fixture("aur.json");
+		let targets = ["split-two".to_string(), "app".to_string()];
+		let (_, _, graph) = aur_rpc_utils::recursive_info(&targets, &alpm, &aur, None).unwrap();
+		let order = graph.build_order().unwrap();
+		let order = order
+			.into_iter()
+			.map(|stage| stage.into_iter().sorted().collect_vec())
+			.collect_vec();
+		assert_eq!(
+			order,
+			[
+				vec!["libb", "libtest-helper"],
+				vec!["liba", "split"],
+				vec!["app"]
+			]
+		);
 	}
 }
@@ -0,0 +1,75 @@
+use crate::review_ledger;
+use crate::review_ledger::LedgerEntry;
+use crate::rua_paths;
+use colored::*;
+use prettytable::format::*;
+use prettytable::*;
+use std::path::Path;
+
+/// Show the review ledger, see `crate::cli_args::Action::Ledger` for details
+pub fn action_ledger(pkgbase: &Option<String>) {
+	let ledger_dir = rua_paths::global_ledger_dir();
+	let rows: Vec<(String, LedgerEntry)> = match pkgbase {
+		Some(pkgbase) => {
+			let path = rua_paths::ledger_path(&ledger_dir, pkgbase);
+			if !path.exists() {
+				eprintln!("No reviews of {} are recorded", pkgbase);
+				std::process::exit(1)
+			}
+			let entries = review_ledger::read(&path).into_iter();
+			entries.map(|entry| (pkgbase.to_string(), entry)).collect()
+		}
+		None => latest_approvals(&ledger_dir),
+	};
+	let mut table = Table::new();
+	table.set_titles(row![
+		"Package".underline(),
+		"Reviewed (UTC)".underline(),
+		"User".underline(),
+		"Commit".underline(),
+		"Tree".underline(),
+		"Local changes".underline()
+	]);
+	for (pkgbase, entry) in rows {
+		let user = if entry.noconfirm {
+			format!("{} (--noconfirm)", entry.user)
+		} else {
+			entry.user
+		};
+		let local_changes = if entry.local_changes {
+			"yes".bright_cyan()
+		} else {
+			"none".normal()
+		};
+		table.add_row(row![
+			pkgbase.yellow(),
+			entry.timestamp,
+			user,
+			entry.commit,
+			entry.tree,
+			local_changes
+		]);
+	}
+	let fmt: TableFormat = FormatBuilder::new().padding(0, 1).build();
+	table.set_format(fmt);
+	table.printstd();
+}
+
+/// The last approval of every pkgbase that has a ledger
+fn latest_approvals(ledger_dir: &Path) -> Vec<(String, LedgerEntry)> {
+	let dir = match ledger_dir.read_dir() {
+		Ok(dir) => dir,
+		Err(_) => return Vec::new(),
+	};
+	let mut result: Vec<(String, LedgerEntry)> = dir
+		.filter_map(|entry| entry.ok())
+		.filter_map(|entry| {
+			let file_name = entry.file_name().into_string().ok()?;
+			let pkgbase = file_name.strip_suffix(".jsonl")?.to_string();
+			let latest = review_ledger::read(&entry.path()).pop()?;
+			Some((pkgbase, latest))
+		})
+		.collect();
+	result.sort_by(|a, b| a.0.cmp(&b.0));
+	result
+}
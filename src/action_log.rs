@@ -0,0 +1,52 @@
+use crate::build_log;
+use crate::rua_paths;
+use crate::terminal_util;
+use colored::Colorize;
+use prettytable::format::FormatBuilder;
+use prettytable::row;
+use prettytable::Table;
+
+/// List or view build logs, see `crate::cli_args::Action::Log` for details
+pub fn action_log(pkgbase: &str, number: Option<usize>) {
+	let logs_dir = rua_paths::global_logs_dir().join(pkgbase);
+	let logs = build_log::list_logs(&logs_dir);
+	if logs.is_empty() {
+		eprintln!("No build logs found for {}", pkgbase);
+		std::process::exit(1)
+	}
+	match number {
+		None => {
+			let mut table = Table::new();
+			table.set_titles(row![
+				"#".underline(),
+				"Started (UTC)".underline(),
+				"Version".underline(),
+				"Log".underline()
+			]);
+			for (index, log) in logs.iter().enumerate() {
+				table.add_row(row![
+					index + 1,
+					log.started,
+					log.version.yellow(),
+					log.path.display()
+				]);
+			}
+			table.set_format(FormatBuilder::new().padding(0, 1).build());
+			table.printstd();
+		}
+		Some(number) => {
+			let log = number.checked_sub(1).and_then(|index| logs.get(index));
+			let log = log.unwrap_or_else(|| {
+				eprintln!(
+					"There is no build log number {}, {} has {} logs",
+					number,
+					pkgbase,
+					logs.len()
+				);
+				std::process::exit(1)
+			});
+			let path = log.path.to_str().expect("Non-UTF8 log path");
+			terminal_util::run_env_command(&logs_dir, "PAGER", "less", &[path]);
+		}
+	}
+}
@@ -0,0 +1,194 @@
+use crate::cli_args::PatchesAction;
+use crate::git_utils;
+use crate::git_utils::Patch;
+use crate::rua_paths::RuaPaths;
+use colored::*;
+use itertools::Itertools;
+use prettytable::format::*;
+use prettytable::*;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Manage local commits of a review repository, see `crate::cli_args::PatchesAction`
+pub fn action_patches(action: &PatchesAction, rua_paths: &RuaPaths) {
+	match action {
+		PatchesAction::List { target } => {
+			let dir = reviewed_dir(target, rua_paths);
+			list(&git_utils::local_patches(&dir, rua_paths));
+		}
+		PatchesAction::Export { target, output } => {
+			let dir = reviewed_dir(target, rua_paths);
+			let output = output.clone().unwrap_or_else(|| PathBuf::from("."));
+			export(&dir, &output, rua_paths);
+		}
+		PatchesAction::Drop { target, number } => {
+			let dir = reviewed_dir(target, rua_paths);
+			let patches = git_utils::local_patches(&dir, rua_paths);
+			if *number == 0 || *number > patches.len() {
+				eprintln!(
+					"There is no patch number {}, {} has {} local patches",
+					number,
+					target,
+					patches.len()
+				);
+				std::process::exit(1)
+			}
+			// replaying on top of the upstream commit that is merged now,
+			// so that new upstream changes are not pulled in without a review
+			let base = git_utils::merge_base(&dir, "HEAD", "upstream/master", rua_paths)
+				.unwrap_or_else(|| {
+					eprintln!("No upstream commit is merged in {:?}", dir);
+					std::process::exit(1)
+				});
+			let kept = patches
+				.iter()
+				.enumerate()
+				.filter(|(index, _)| index + 1 != *number)
+				.map(|(_, patch)| patch)
+				.collect_vec();
+			replay(&dir, &base, &kept, rua_paths);
+			eprintln!("Dropped patch {}", patches[number - 1].subject);
+		}
+		PatchesAction::Rebase { target } => {
+			let dir = reviewed_dir(target, rua_paths);
+			let merged = git_utils::merge_base(&dir, "HEAD", "upstream/master", rua_paths);
+			git_utils::fetch(target, &dir, rua_paths);
+			let patches = git_utils::local_patches(&dir, rua_paths);
+			replay(
+				&dir,
+				"upstream/master",
+				&patches.iter().collect_vec(),
+				rua_paths,
+			);
+			// the next review diffs the new upstream against the one reviewed before,
+			// after several rebases without a review that is the first one
+			if let Some(merged) = merged {
+				let upstream = git_utils::rev_parse(&dir, "upstream/master", rua_paths);
+				if upstream.as_ref() != Some(&merged)
+					&& !git_utils::has_unreviewed_rebase(&dir, rua_paths)
+				{
+					let name = git_utils::REVIEWED_UPSTREAM_REF;
+					git_utils::update_ref(&dir, name, &merged, rua_paths);
+				}
+			}
+			eprintln!(
+				"Rebased {} local patches onto upstream. Upstream changes are not reviewed yet,",
+				patches.len()
+			);
+			eprintln!(
+				"the next review shows them and asks you to view them before using the package."
+			);
+		}
+	}
+}
+
+fn reviewed_dir(pkgbase: &str, rua_paths: &RuaPaths) -> PathBuf {
+	let dir = rua_paths.review_dir(pkgbase);
+	if !dir.exists() || git_utils::rev_parse(&dir, "HEAD", rua_paths).is_none() {
+		eprintln!("{} was not reviewed yet", pkgbase);
+		std::process::exit(1)
+	}
+	dir
+}
+
+fn list(patches: &[Patch]) {
+	if patches.is_empty() {
+		eprintln!("No local patches, the package is the same as upstream AUR");
+		return;
+	}
+	let mut table = Table::new();
+	table.set_titles(row![
+		"#".underline(),
+		"Commit".underline(),
+		"Date".underline(),
+		"Subject".underline()
+	]);
+	for (index, patch) in patches.iter().enumerate() {
+		table.add_row(row![
+			index + 1,
+			&patch.commit[..12.min(patch.commit.len())],
+			patch.date,
+			patch.subject.yellow()
+		]);
+	}
+	let fmt: TableFormat = FormatBuilder::new().padding(0, 1).build();
+	table.set_format(fmt);
+	table.printstd();
+}
+
+fn export(dir: &Path, output: &Path, rua_paths: &RuaPaths) {
+	let patches = git_utils::local_patches(dir, rua_paths);
+	fs::create_dir_all(output)
+		.unwrap_or_else(|err| panic!("Failed to create directory {:?}, {}", output, err));
+	for (index, patch) in patches.iter().enumerate() {
+		let file = output.join(patch_file_name(index + 1, &patch.subject));
+		fs::write(
+			&file,
+			git_utils::format_patch(dir, &patch.commit, rua_paths),
+		)
+		.unwrap_or_else(|err| panic!("Failed to write patch {:?}, {}", file, err));
+		eprintln!("{}", file.display());
+	}
+}
+
+/// Same naming as `git format-patch`, like 0001-fix-build-with-gcc-14.patch
+fn patch_file_name(number: usize, subject: &str) -> String {
+	let slug = subject
+		.split(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '_')
+		.filter(|word| !word.is_empty())
+		.join("-");
+	let slug: String = slug.chars().take(52).collect();
+	format!("{:04}-{}.patch", number, slug.trim_end_matches(['-', '.']))
+}
+
+/// Puts the patches on top of `onto`, one by one.
+/// On a conflict, goes back to where the repository was before and exits,
+/// so that a half-applied series is never reviewed or built.
+fn replay(dir: &Path, onto: &str, patches: &[&Patch], rua_paths: &RuaPaths) {
+	if !git_utils::is_clean(dir, rua_paths) {
+		eprintln!(
+			"There are uncommitted changes in {:?}, commit or discard them first",
+			dir
+		);
+		std::process::exit(1)
+	}
+	let previous = git_utils::rev_parse(dir, "HEAD", rua_paths)
+		.expect("Failed to get the current commit of the review repository");
+	git_utils::reset_hard(dir, onto, rua_paths);
+	for patch in patches {
+		if git_utils::cherry_pick(dir, &patch.commit, rua_paths) {
+			continue;
+		}
+		eprintln!(
+			"\n{} patch {} does not apply, conflicting files:",
+			"Conflict:".bold().red(),
+			patch.subject.yellow()
+		);
+		for file in git_utils::conflicted_files(dir, rua_paths) {
+			eprintln!("  {}", file);
+		}
+		git_utils::reset_hard(dir, &previous, rua_paths);
+		eprintln!("\nThe local commits in {:?} are back as they were.", dir);
+		eprintln!("To go on, drop or rework the conflicting patch, or review the package");
+		eprintln!("to merge upstream changes into your patches instead of rebasing them.");
+		std::process::exit(1)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::action_patches::*;
+
+	#[test]
+	fn test_patch_file_name() {
+		assert_eq!(
+			patch_file_name(1, "Fix build with GCC 14 (again)"),
+			"0001-Fix-build-with-GCC-14-again.patch"
+		);
+		assert_eq!(
+			patch_file_name(12, "pkgver: 1.2.3"),
+			"0012-pkgver-1.2.3.patch"
+		);
+	}
+}
@@ -0,0 +1,53 @@
+use crate::alpm_wrapper::new_alpm_wrapper;
+use crate::aur_rpc_utils;
+use crate::aur_wrapper::new_aur_wrapper;
+use crate::reviewing;
+use crate::rua_paths::RuaPaths;
+use colored::Colorize;
+use indexmap::IndexSet;
+use itertools::Itertools;
+use std::fs;
+
+/// Fetch and review packages without building them, see `crate::cli_args::Action::Review`
+pub fn action_review(targets: &[String], all_installed: bool, rua_paths: &RuaPaths) {
+	let mut targets = targets.to_vec();
+	if all_installed {
+		let alpm = new_alpm_wrapper();
+		let installed = alpm
+			.get_non_pacman_packages()
+			.expect("Failed to get the list of installed non-pacman packages");
+		targets.extend(installed.into_iter().map(|(name, _version)| name));
+	}
+	let aur = new_aur_wrapper();
+	let info =
+		aur_rpc_utils::info_map(&targets, &*aur).expect("Failed to get package info from AUR");
+	let not_found = targets
+		.iter()
+		.filter(|target| !info.contains_key(*target))
+		.collect_vec();
+	if !not_found.is_empty() && !all_installed {
+		eprintln!(
+			"Need to review {}, but they were not found on AUR",
+			not_found.iter().format(", ")
+		);
+		std::process::exit(1)
+	}
+	for name in not_found {
+		eprintln!("Skipping {}, it is not on AUR", name);
+	}
+	let pkgbases: IndexSet<&str> = targets
+		.iter()
+		.filter_map(|target| info.get(target))
+		.map(|package| package.package_base.as_str())
+		.collect();
+	for pkgbase in &pkgbases {
+		eprintln!("\nFetching {}...", pkgbase.yellow());
+		let dir = rua_paths.review_dir(pkgbase);
+		fs::create_dir_all(&dir).unwrap_or_else(|err| {
+			panic!("Failed to create repository dir for {}, {}", pkgbase, err)
+		});
+		reviewing::fetch_repo(&dir, pkgbase, rua_paths);
+		reviewing::review_fetched_repo(&dir, pkgbase, rua_paths, None);
+	}
+	eprintln!("Reviewed {} packages, nothing was built.", pkgbases.len());
+}
@@ -1,5 +1,5 @@
+use crate::aur_wrapper::new_aur_wrapper;
 use crate::print_package_table;
-use raur::blocking::Raur;
 use raur::Package;
 use raur::SearchBy;
 
@@ -18,8 +18,7 @@ pub fn action_search(keywords: &[String]) {
 	let query = keywords
 		.first()
 		.expect("Zero search arguments, should be impossible in structopt");
-	let raur_handle = raur::blocking::Handle::new();
-	let result = raur_handle.search_by(query, SearchBy::NameDesc);
+	let result = new_aur_wrapper().search_by(query, SearchBy::NameDesc);
 	match result {
 		Ok(mut result) => {
 			if result.is_empty() {
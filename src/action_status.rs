@@ -0,0 +1,86 @@
+use crate::alpm_wrapper::new_alpm_wrapper;
+use crate::git_utils;
+use crate::review_ledger;
+use crate::rua_paths::RuaPaths;
+use colored::*;
+use itertools::Itertools;
+use prettytable::format::*;
+use prettytable::*;
+use srcinfo::Srcinfo;
+use std::path::Path;
+
+/// Show the state of all review directories, see `crate::cli_args::Action::Status`
+pub fn action_status(fetch: bool, rua_paths: &RuaPaths) {
+	let pkgbases = rua_paths.reviewed_pkgbases();
+	if pkgbases.is_empty() {
+		eprintln!("No packages were reviewed yet");
+		return;
+	}
+	let alpm = new_alpm_wrapper();
+	let mut table = Table::new();
+	table.set_titles(row![
+		"Package".underline(),
+		"Upstream".underline(),
+		"Local changes".underline(),
+		"Installed".underline(),
+		"Reviewed commit".underline()
+	]);
+	for pkgbase in &pkgbases {
+		let dir = rua_paths.review_dir(pkgbase);
+		if fetch {
+			eprintln!("Fetching {}...", pkgbase);
+			git_utils::fetch(pkgbase, &dir, rua_paths);
+		}
+		let is_upstream_merged = git_utils::is_upstream_merged(&dir, rua_paths);
+		let upstream = if is_upstream_merged {
+			"merged".green()
+		} else {
+			"unmerged changes".yellow()
+		};
+		let local_changes = if !is_upstream_merged {
+			"merge to compare".dimmed()
+		} else if git_utils::identical_to_upstream(&dir, rua_paths) {
+			"none".normal()
+		} else {
+			"yes".bright_cyan()
+		};
+		let pkgnames = pkgnames(&dir).unwrap_or_else(|| vec![pkgbase.to_string()]);
+		let installed = alpm
+			.installed_versions(&pkgnames)
+			.expect("Failed to get installed package versions");
+		let installed = if installed.is_empty() {
+			"no".dimmed()
+		} else {
+			let installed = pkgnames
+				.iter()
+				.filter_map(|name| installed.get(name).map(|version| (name, version)))
+				.map(|(name, version)| format!("{} {}", name, version));
+			installed.format(", ").to_string().normal()
+		};
+		let approval = review_ledger::last_approval(&rua_paths.ledger_path(pkgbase));
+		let commit = approval.map_or_else(
+			|| "never reviewed".dimmed(),
+			|entry| {
+				let commit = entry.commit.get(..7).unwrap_or(&entry.commit);
+				let date = entry.timestamp.get(..10).unwrap_or(&entry.timestamp);
+				format!("{} {}", commit, date).normal()
+			},
+		);
+		table.add_row(row![
+			pkgbase.yellow(),
+			upstream,
+			local_changes,
+			installed,
+			commit
+		]);
+	}
+	let fmt: TableFormat = FormatBuilder::new().padding(0, 1).build();
+	table.set_format(fmt);
+	table.printstd();
+}
+
+/// Package names from the reviewed .SRCINFO, if there is one
+fn pkgnames(dir: &Path) -> Option<Vec<String>> {
+	let srcinfo = Srcinfo::from_path(dir.join(".SRCINFO")).ok()?;
+	Some(srcinfo.pkgs.into_iter().map(|pkg| pkg.pkgname).collect())
+}
@@ -1,7 +1,10 @@
 use crate::action_install;
+use crate::action_install::InstallOptions;
 use crate::alpm_wrapper::new_alpm_wrapper;
 use crate::alpm_wrapper::AlpmWrapper;
 use crate::aur_rpc_utils;
+use crate::aur_wrapper::new_aur_wrapper;
+use crate::aur_wrapper::AurWrapper;
 use crate::pacman;
 use crate::rua_paths::RuaPaths;
 use crate::terminal_util;
@@ -27,8 +30,10 @@ fn pkg_is_devel(name: &str) -> bool {
 
 pub fn upgrade_printonly(devel: bool, ignored: &HashSet<&str>) {
 	let alpm = new_alpm_wrapper();
+	let aur = new_aur_wrapper();
 	let (outdated, nonexistent) =
-		calculate_upgrade(&*alpm, devel, ignored).expect("Calculating upgrade failed");
+		calculate_upgrade(&*alpm, &*aur, devel, ignored, &system_ignored_packages())
+			.expect("Calculating upgrade failed");
 
 	if outdated.is_empty() && nonexistent.is_empty() {
 		eprintln!(
@@ -45,10 +50,17 @@ pub fn upgrade_printonly(devel: bool, ignored: &HashSet<&str>) {
 	}
 }
 
-pub fn upgrade_real(devel: bool, rua_paths: &RuaPaths, ignored: &HashSet<&str>) {
+pub fn upgrade_real(
+	devel: bool,
+	rua_paths: &RuaPaths,
+	ignored: &HashSet<&str>,
+	options: &InstallOptions,
+) {
 	let alpm = new_alpm_wrapper();
+	let aur = new_aur_wrapper();
 	let (outdated, nonexistent) =
-		calculate_upgrade(&*alpm, devel, ignored).expect("calculating upgrade failed");
+		calculate_upgrade(&*alpm, &*aur, devel, ignored, &system_ignored_packages())
+			.expect("calculating upgrade failed");
 
 	if outdated.is_empty() && nonexistent.is_empty() {
 		eprintln!(
@@ -65,12 +77,16 @@ pub fn upgrade_real(devel: bool, rua_paths: &RuaPaths, ignored: &HashSet<&str>)
 	} else {
 		print_outdated(&outdated, &nonexistent);
 		eprintln!();
+		let outdated: Vec<String> = outdated.iter().map(|o| o.0.to_string()).collect();
+		if options.noconfirm.is_some() {
+			action_install::install(&outdated, rua_paths, options);
+			return;
+		}
 		loop {
 			eprint!("Do you wish to upgrade them? [O]=ok, [X]=exit. ");
 			let user_input = terminal_util::read_line_lowercase();
 			if &user_input == "o" {
-				let outdated: Vec<String> = outdated.iter().map(|o| o.0.to_string()).collect();
-				action_install::install(&outdated, rua_paths, false, true);
+				action_install::install(&outdated, rua_paths, options);
 				break;
 			} else if &user_input == "x" {
 				break;
@@ -82,16 +98,20 @@ pub fn upgrade_real(devel: bool, rua_paths: &RuaPaths, ignored: &HashSet<&str>)
 type OutdatedPkgs = Vec<(String, String, String)>;
 type ForeignPkgs = Vec<(String, String)>;
 
+fn system_ignored_packages() -> HashSet<String> {
+	pacman::get_ignored_packages().unwrap_or_else(|err| {
+		warn!("Could not get ignored packages, {}", err);
+		HashSet::new()
+	})
+}
+
 fn calculate_upgrade(
 	alpm: &dyn AlpmWrapper,
+	aur: &dyn AurWrapper,
 	devel: bool,
 	locally_ignored_packages: &HashSet<&str>,
+	system_ignored_packages: &HashSet<String>,
 ) -> Result<(OutdatedPkgs, ForeignPkgs)> {
-	let system_ignored_packages = pacman::get_ignored_packages().unwrap_or_else(|err| {
-		warn!("Could not get ignored packages, {}", err);
-		HashSet::new()
-	});
-
 	let aur_pkgs = alpm.get_non_pacman_packages()?;
 
 	let aur_pkgs_string = aur_pkgs
@@ -107,7 +127,7 @@ fn calculate_upgrade(
 	let mut nonexistent = Vec::new();
 	let mut ignored = Vec::new();
 
-	let info_map = aur_rpc_utils::info_map(&aur_pkgs.iter().map(|(p, _)| p).collect_vec());
+	let info_map = aur_rpc_utils::info_map(&aur_pkgs.iter().map(|(p, _)| p).collect_vec(), aur);
 	let info_map = info_map.unwrap_or_else(|err| panic!("Failed to get AUR information: {}", err));
 
 	for (pkg, local_ver) in aur_pkgs {
@@ -166,3 +186,49 @@ fn print_outdated(outdated: &[(String, String, String)], nonexistent: &[(String,
 	table.set_format(fmt);
 	table.printstd();
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::action_upgrade::*;
+	use crate::fake_wrappers::FakeAlpm;
+	use crate::fake_wrappers::FakeAur;
+
+	fn upgrade(devel: bool) -> (OutdatedPkgs, ForeignPkgs) {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let aur = FakeAur::from_fixture("aur.json");
+		let locally_ignored = ["locally-ignored"].into_iter().collect();
+		let system_ignored = ["system-ignored".to_string()].into_iter().collect();
+		calculate_upgrade(&alpm, &aur, devel, &locally_ignored, &system_ignored).unwrap()
+	}
+
+	#[test]
+	fn test_calculate_upgrade() {
+		let (outdated, nonexistent) = upgrade(false);
+		assert_eq!(
+			outdated,
+			[(
+				"libold".to_string(),
+				"1.0-1".to_string(),
+				"1.2-1".to_string()
+			)]
+		);
+		assert_eq!(
+			nonexistent,
+			[("removed-from-aur".to_string(), "0.1-1".to_string())]
+		);
+	}
+
+	#[test]
+	fn test_calculate_upgrade_devel() {
+		let (outdated, _) = upgrade(true);
+		let outdated = outdated.into_iter().map(|o| o.0).collect_vec();
+		assert_eq!(outdated, ["libold", "tool-git"]);
+	}
+
+	#[test]
+	fn test_pkg_is_devel() {
+		assert!(pkg_is_devel("tool-git"));
+		assert!(pkg_is_devel("tool-hg-qt5"));
+		assert!(!pkg_is_devel("gitk"));
+	}
+}
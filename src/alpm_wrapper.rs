@@ -2,14 +2,37 @@ use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use itertools::Itertools;
+#[cfg(feature = "libalpm")]
+use log::warn;
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::process::Command;
 use std::process::Stdio;
 
+/// Uses libalpm directly if RUA is built with the `libalpm` feature,
+/// falling back to running `pacman` binaries if libalpm cannot be initialized.
 pub fn new_alpm_wrapper() -> Box<dyn AlpmWrapper> {
+	#[cfg(feature = "libalpm")]
+	match AlpmLibWrapper::new() {
+		Ok(wrapper) => return Box::new(wrapper),
+		Err(err) => warn!(
+			"Failed to initialize libalpm, falling back to pacman binaries. {:#}",
+			err
+		),
+	}
 	Box::new(AlpmBinWrapper {})
 }
 
+/// An installed or sync repository package, with what it provides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provider {
+	pub name: String,
+	pub version: String,
+	/// Entries like `sh` or `java-runtime=11`
+	pub provides: Vec<String>,
+}
+
 pub trait AlpmWrapper {
 	/// Checks if either this package is installed, or anything that provides the name is
 	fn is_installed(&self, package: &str) -> Result<bool>;
@@ -17,9 +40,57 @@ pub trait AlpmWrapper {
 	/// Checks if either this package is installable, or anything that provides the name is
 	fn is_installable(&self, package: &str) -> Result<bool>;
 
+	/// Returns the version of the installed package with exactly this name, if any
+	fn installed_version(&self, package: &str) -> Result<Option<String>>;
+
+	/// Returns those of the packages that are installed, see `is_installed`
+	fn installed_subset(&self, packages: &[String]) -> Result<HashSet<String>> {
+		let mut result = HashSet::new();
+		for package in packages {
+			if self.is_installed(package)? {
+				result.insert(package.to_string());
+			}
+		}
+		Ok(result)
+	}
+
+	/// Returns those of the packages that are installable, see `is_installable`
+	fn installable_subset(&self, packages: &[String]) -> Result<HashSet<String>> {
+		let mut result = HashSet::new();
+		for package in packages {
+			if self.is_installable(package)? {
+				result.insert(package.to_string());
+			}
+		}
+		Ok(result)
+	}
+
+	/// Returns the versions of installed packages, see `installed_version`
+	fn installed_versions(&self, packages: &[String]) -> Result<HashMap<String, String>> {
+		let mut result = HashMap::new();
+		for package in packages {
+			if let Some(version) = self.installed_version(package)? {
+				result.insert(package.to_string(), version);
+			}
+		}
+		Ok(result)
+	}
+
+	/// Returns the installed packages satisfying any of the dependencies (like `foo` or `foo>=2`),
+	/// either by name or through what they provide
+	fn installed_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>>;
+
+	/// Returns the sync repository packages satisfying any of the dependencies,
+	/// either by name or through what they provide
+	fn repo_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>>;
+
 	/// Returns a list of (package, version)
 	fn get_non_pacman_packages(&self) -> Result<Vec<(String, String)>>;
 
+	/// Returns the installed package owning each of the files, for files owned by any.
+	/// Paths are relative to the root directory, as in package archives.
+	fn file_owners(&self, files: &[String]) -> Result<HashMap<String, String>>;
+
 	/// Compares package versions according to
 	/// https://archlinux.org/pacman/vercmp.8.html
 	fn version_compare(&self, a: &str, b: &str) -> Result<Ordering>;
@@ -61,6 +132,125 @@ impl AlpmWrapper for AlpmBinWrapper {
 		Ok(result.success())
 	}
 
+	fn installed_version(&self, package: &str) -> Result<Option<String>> {
+		let output = Command::new("pacman")
+			.args(["-Q", "--color=never", "--", package])
+			.stderr(Stdio::null())
+			.output()
+			.with_context(|| format!("Failed to get installed version of {}", package))?;
+		let stdout =
+			String::from_utf8(output.stdout).context("failed to parse pacman output as utf8")?;
+		Ok(version_for_name(&stdout, package))
+	}
+
+	fn installed_subset(&self, packages: &[String]) -> Result<HashSet<String>> {
+		if packages.is_empty() {
+			return Ok(HashSet::new());
+		}
+		// same query as `pacman -Qi` in `is_installed`, for all the packages at once
+		let output = Command::new("pacman")
+			.args(["-Q", "--"])
+			.args(packages)
+			.env("LC_ALL", "C")
+			.stdout(Stdio::null())
+			.output()
+			.context("Failed to determine which packages are installed")?;
+		let stderr =
+			String::from_utf8(output.stderr).context("failed to parse pacman output as utf8")?;
+		let missing = packages_not_found(&stderr);
+		if !output.status.success() && missing.is_empty() {
+			return Err(anyhow!(
+				"pacman -Q failed with exit code {:?}, {}",
+				output.status.code(),
+				stderr.trim()
+			));
+		}
+		let installed = packages.iter().filter(|p| !missing.contains(p.as_str()));
+		Ok(installed.cloned().collect())
+	}
+
+	fn installable_subset(&self, packages: &[String]) -> Result<HashSet<String>> {
+		if packages.is_empty() {
+			return Ok(HashSet::new());
+		}
+		let output = Command::new("pacman")
+			.args(["-Sddp", "--print-format", "%n", "--"])
+			.args(packages)
+			.env("LC_ALL", "C")
+			.output()
+			.context("Failed to determine which packages are installable")?;
+		let stderr =
+			String::from_utf8(output.stderr).context("failed to parse pacman output as utf8")?;
+		let not_found = targets_not_found(&stderr);
+		if !output.status.success() && not_found.is_empty() {
+			return Err(anyhow!(
+				"pacman -Sddp failed with exit code {:?}, {}",
+				output.status.code(),
+				stderr.trim()
+			));
+		}
+		let installable = packages.iter().filter(|p| !not_found.contains(p.as_str()));
+		Ok(installable.cloned().collect())
+	}
+
+	fn installed_versions(&self, packages: &[String]) -> Result<HashMap<String, String>> {
+		if packages.is_empty() {
+			return Ok(HashMap::new());
+		}
+		let output = Command::new("pacman")
+			.args(["-Q", "--color=never", "--"])
+			.args(packages)
+			.stderr(Stdio::null())
+			.output()
+			.context("Failed to get installed versions")?;
+		let stdout =
+			String::from_utf8(output.stdout).context("failed to parse pacman output as utf8")?;
+		Ok(versions_for_names(&stdout, packages))
+	}
+
+	fn installed_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		if dependencies.is_empty() {
+			return Ok(Vec::new());
+		}
+		// `pacman -Q` falls back to the packages providing a target
+		let output = Command::new("pacman")
+			.args(["-Qi", "--color=never", "--"])
+			.args(dependencies)
+			.env("LC_ALL", "C")
+			.stderr(Stdio::null())
+			.output()
+			.context("Failed to get installed package providers")?;
+		let stdout =
+			String::from_utf8(output.stdout).context("failed to parse pacman output as utf8")?;
+		Ok(providers_from_info(&stdout))
+	}
+
+	fn repo_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		let installable = self.installable_subset(dependencies)?;
+		if installable.is_empty() {
+			return Ok(Vec::new());
+		}
+		// `pacman -Sddp` picks the package satisfying each dependency, `pacman -Si` shows its provides
+		let output = Command::new("pacman")
+			.args(["-Sddp", "--print-format", "%n", "--"])
+			.args(&installable)
+			.stderr(Stdio::null())
+			.output()
+			.context("Failed to get repository package providers")?;
+		let stdout =
+			String::from_utf8(output.stdout).context("failed to parse pacman output as utf8")?;
+		let output = Command::new("pacman")
+			.args(["-Si", "--color=never", "--"])
+			.args(stdout.lines().map(str::trim).unique())
+			.env("LC_ALL", "C")
+			.stderr(Stdio::null())
+			.output()
+			.context("Failed to get repository package providers")?;
+		let stdout =
+			String::from_utf8(output.stdout).context("failed to parse pacman output as utf8")?;
+		Ok(providers_from_info(&stdout))
+	}
+
 	fn get_non_pacman_packages(&self) -> Result<Vec<(String, String)>> {
 		let mut command = Command::new("pacman");
 		command.args(["-Q", "--foreign", "--color=never"]);
@@ -83,6 +273,32 @@ impl AlpmWrapper for AlpmBinWrapper {
 		Ok(result)
 	}
 
+	fn file_owners(&self, files: &[String]) -> Result<HashMap<String, String>> {
+		let mut result = HashMap::new();
+		// Many files per query, but within the limits of the command line length
+		for chunk in files.chunks(1000) {
+			let output = Command::new("pacman")
+				.args(["-Qo", "--color=never", "--"])
+				.args(chunk.iter().map(|file| format!("/{}", file)))
+				.env("LC_ALL", "C")
+				.output()
+				.context("Failed to find the owners of files")?;
+			let stderr = String::from_utf8(output.stderr)
+				.context("failed to parse pacman output as utf8")?;
+			// pacman fails if any of the files is not owned, which is not an error here
+			let unexpected = stderr
+				.lines()
+				.find(|line| !line.starts_with("error: No package owns "));
+			if let Some(line) = unexpected {
+				return Err(anyhow!("pacman -Qo failed, {}", line));
+			}
+			let stdout = String::from_utf8(output.stdout)
+				.context("failed to parse pacman output as utf8")?;
+			result.extend(owners_from_query(&stdout));
+		}
+		Ok(result)
+	}
+
 	fn version_compare(&self, a: &str, b: &str) -> Result<Ordering> {
 		let mut command = Command::new("vercmp");
 		command.args([a, b]);
@@ -97,16 +313,270 @@ impl AlpmWrapper for AlpmBinWrapper {
 	}
 }
 
+/// Finds the version of `package` in "name version" lines.
+/// Lines of other packages, for example those providing `package`, are ignored.
+fn version_for_name(output: &str, package: &str) -> Option<String> {
+	output
+		.lines()
+		.filter_map(|line| line.split_once(' '))
+		.find(|(name, _version)| *name == package)
+		.map(|(_name, version)| version.trim().to_string())
+}
+
+/// Same as `version_for_name`, but for all the packages at once
+fn versions_for_names(output: &str, packages: &[String]) -> HashMap<String, String> {
+	output
+		.lines()
+		.filter_map(|line| line.split_once(' '))
+		.filter(|(name, _version)| packages.iter().any(|package| package == name))
+		.map(|(name, version)| (name.to_string(), version.trim().to_string()))
+		.collect()
+}
+
+/// Parses the `Name`, `Version` and `Provides` fields of `pacman -Qi` or `pacman -Si`,
+/// run with LC_ALL=C. Packages are separated by empty lines.
+fn providers_from_info(output: &str) -> Vec<Provider> {
+	let mut result = Vec::new();
+	for block in output.split("\n\n") {
+		let fields: HashMap<&str, &str> = block
+			.lines()
+			.filter_map(|line| line.split_once(" : "))
+			.map(|(key, value)| (key.trim(), value.trim()))
+			.collect();
+		if let (Some(name), Some(version)) = (fields.get("Name"), fields.get("Version")) {
+			let provides = match fields.get("Provides") {
+				Some(&"None") | None => Vec::new(),
+				Some(provides) => provides.split_whitespace().map(str::to_string).collect(),
+			};
+			result.push(Provider {
+				name: name.to_string(),
+				version: version.to_string(),
+				provides,
+			});
+		}
+	}
+	result
+}
+
+/// Parses "/path is owned by package version" lines of `pacman -Qo`, run with LC_ALL=C
+fn owners_from_query(output: &str) -> HashMap<String, String> {
+	output
+		.lines()
+		.filter_map(|line| line.rsplit_once(" is owned by "))
+		.filter_map(|(path, owner)| {
+			let (package, _version) = owner.split_once(' ')?;
+			Some((
+				path.trim_start_matches('/').to_string(),
+				package.to_string(),
+			))
+		})
+		.collect()
+}
+
+/// Parses "error: target not found: foo" lines of pacman, run with LC_ALL=C
+fn targets_not_found(stderr: &str) -> HashSet<&str> {
+	stderr
+		.lines()
+		.filter_map(|line| line.strip_prefix("error: target not found: "))
+		.map(str::trim)
+		.collect()
+}
+
+/// Parses "error: package 'foo' was not found" lines of `pacman -Q`, run with LC_ALL=C
+fn packages_not_found(stderr: &str) -> HashSet<&str> {
+	stderr
+		.lines()
+		.filter_map(|line| line.strip_prefix("error: package '"))
+		.filter_map(|line| line.strip_suffix("' was not found"))
+		.collect()
+}
+
+/// Queries the local and sync databases in-process, without forking a process per query.
+#[cfg(feature = "libalpm")]
+struct AlpmLibWrapper {
+	handle: alpm::Alpm,
+}
+
+#[cfg(feature = "libalpm")]
+impl AlpmLibWrapper {
+	fn new() -> Result<AlpmLibWrapper> {
+		let root = pacman_conf(&["RootDir"])?;
+		let db_path = pacman_conf(&["DBPath"])?;
+		let handle = alpm::Alpm::new(root.trim(), db_path.trim()).with_context(|| {
+			format!(
+				"Failed to open pacman database, root {}, database path {}",
+				root.trim(),
+				db_path.trim()
+			)
+		})?;
+		for repo in pacman_conf(&["--repo-list"])?.lines() {
+			handle
+				.register_syncdb(repo, alpm::SigLevel::USE_DEFAULT)
+				.with_context(|| format!("Failed to register sync database {}", repo))?;
+		}
+		Ok(AlpmLibWrapper { handle })
+	}
+}
+
+#[cfg(feature = "libalpm")]
+fn pacman_conf(args: &[&str]) -> Result<String> {
+	let output = Command::new("pacman-conf")
+		.args(args)
+		.output()
+		.with_context(|| format!("Failed to execute pacman-conf {}", args.join(" ")))?;
+	if !output.status.success() {
+		return Err(anyhow!(
+			"pacman-conf {} failed with exit code {:?}",
+			args.join(" "),
+			output.status.code()
+		));
+	}
+	String::from_utf8(output.stdout).context("Failed to parse pacman-conf output as utf8")
+}
+
+#[cfg(feature = "libalpm")]
+impl AlpmWrapper for AlpmLibWrapper {
+	fn is_installed(&self, package: &str) -> Result<bool> {
+		Ok(self
+			.handle
+			.localdb()
+			.pkgs()
+			.find_satisfier(package)
+			.is_some())
+	}
+
+	fn is_installable(&self, package: &str) -> Result<bool> {
+		Ok(self.handle.syncdbs().find_satisfier(package).is_some())
+	}
+
+	fn installed_version(&self, package: &str) -> Result<Option<String>> {
+		let version = self.handle.localdb().pkg(package).ok();
+		Ok(version.map(|pkg| pkg.version().as_str().to_string()))
+	}
+
+	fn installed_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		let localdb = self.handle.localdb();
+		let result = dependencies
+			.iter()
+			.filter_map(|dependency| {
+				localdb
+					.pkg(dependency.as_str())
+					.ok()
+					.or_else(|| localdb.pkgs().find_satisfier(dependency.as_str()))
+			})
+			.map(provider)
+			.collect();
+		Ok(result)
+	}
+
+	fn repo_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		let syncdbs = self.handle.syncdbs();
+		let result = dependencies
+			.iter()
+			.filter_map(|dependency| syncdbs.find_satisfier(dependency.as_str()))
+			.map(provider)
+			.collect();
+		Ok(result)
+	}
+
+	fn get_non_pacman_packages(&self) -> Result<Vec<(String, String)>> {
+		let syncdbs = self.handle.syncdbs();
+		let result = self
+			.handle
+			.localdb()
+			.pkgs()
+			.iter()
+			.filter(|pkg| syncdbs.iter().all(|db| db.pkg(pkg.name()).is_err()))
+			.map(|pkg| (pkg.name().to_string(), pkg.version().as_str().to_string()))
+			.collect();
+		Ok(result)
+	}
+
+	fn file_owners(&self, files: &[String]) -> Result<HashMap<String, String>> {
+		let files: HashSet<&str> = files.iter().map(String::as_str).collect();
+		let mut result = HashMap::new();
+		for pkg in self.handle.localdb().pkgs() {
+			for file in pkg.files().files() {
+				let path = String::from_utf8_lossy(file.name().as_ref());
+				if files.contains(path.as_ref()) {
+					result.insert(path.into_owned(), pkg.name().to_string());
+				}
+			}
+		}
+		Ok(result)
+	}
+
+	fn version_compare(&self, a: &str, b: &str) -> Result<Ordering> {
+		Ok(alpm::vercmp(a, b))
+	}
+}
+
+#[cfg(feature = "libalpm")]
+fn provider(pkg: &alpm::Package) -> Provider {
+	Provider {
+		name: pkg.name().to_string(),
+		version: pkg.version().as_str().to_string(),
+		provides: pkg.provides().iter().map(|dep| dep.to_string()).collect(),
+	}
+}
+
 #[cfg(test)]
-#[cfg(feature = "testpacman")]
 mod tests {
-	use crate::alpm_wrapper::AlpmBinWrapper;
-	use crate::alpm_wrapper::AlpmWrapper;
+	use crate::alpm_wrapper::*;
 
 	#[test]
+	fn test_pacman_output_parsing() {
+		let packages = ["foo".to_string(), "bar".to_string()];
+		let versions = versions_for_names("foo 1.0-1\nbash 5.2-1\nbar 2:3-4\n", &packages);
+		assert_eq!(versions.len(), 2);
+		assert_eq!(versions["foo"], "1.0-1");
+		assert_eq!(versions["bar"], "2:3-4");
+		let stderr = "error: target not found: foo\nerror: target not found: libbar.so\n";
+		let not_found = targets_not_found(stderr);
+		assert_eq!(not_found, HashSet::from(["foo", "libbar.so"]));
+		let stderr = "error: package 'foo' was not found\nerror: package 'a b' was not found\n";
+		assert_eq!(packages_not_found(stderr), HashSet::from(["foo", "a b"]));
+		let owners = owners_from_query(
+			"/usr/bin/foo bar is owned by foo 1.0-1\n/usr/bin/bash is owned by bash 5.2-1\n",
+		);
+		assert_eq!(owners.len(), 2);
+		assert_eq!(owners["usr/bin/foo bar"], "foo");
+		assert_eq!(owners["usr/bin/bash"], "bash");
+		let providers = providers_from_info(
+			"Name            : jre8\nVersion         : 8.1-1\nProvides        : java-runtime=8  java-environment=8\n\nName            : bash\nVersion         : 5.2-1\nProvides        : None\n",
+		);
+		assert_eq!(providers.len(), 2);
+		assert_eq!(
+			providers[0].provides,
+			["java-runtime=8", "java-environment=8"]
+		);
+		assert_eq!(providers[1].name, "bash");
+		assert!(providers[1].provides.is_empty());
+	}
+
+	#[test]
+	#[cfg(feature = "testpacman")]
 	fn test_alpm_bin_wrapper() {
 		let alpm_bin = AlpmBinWrapper {};
 		let packages = alpm_bin.get_non_pacman_packages().unwrap();
 		assert!(packages.iter().all(|(pkg, _ver)| pkg != "pacman"));
 	}
+
+	#[test]
+	#[cfg(all(feature = "testpacman", feature = "libalpm"))]
+	fn test_alpm_lib_wrapper_matches_bin_wrapper() {
+		let alpm_bin = AlpmBinWrapper {};
+		let alpm_lib = AlpmLibWrapper::new().unwrap();
+		let mut bin_packages = alpm_bin.get_non_pacman_packages().unwrap();
+		let mut lib_packages = alpm_lib.get_non_pacman_packages().unwrap();
+		bin_packages.sort();
+		lib_packages.sort();
+		assert_eq!(bin_packages, lib_packages);
+		assert!(alpm_lib.is_installed("pacman").unwrap());
+		assert!(alpm_lib.is_installable("pacman").unwrap());
+		assert_eq!(
+			alpm_lib.version_compare("1.0-1", "1.0-2").unwrap(),
+			Ordering::Less
+		);
+	}
 }
@@ -1,76 +1,470 @@
 use crate::alpm_wrapper::AlpmWrapper;
+use crate::alpm_wrapper::Provider;
+use crate::aur_wrapper::AurWrapper;
+use crate::dependency_graph::DependencyGraph;
+use crate::dependency_graph::DependencyKind;
+use crate::dependency_graph::NodeKind;
+use crate::noconfirm;
+use crate::noconfirm::NoConfirm;
+use crate::terminal_util;
 use anyhow::Result;
+use colored::Colorize;
 use indexmap::IndexMap;
 use indexmap::IndexSet;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use log::trace;
-use raur::blocking::Handle;
-use raur::blocking::Raur;
 use raur::Package;
+use raur::SearchBy;
 use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
 
 type RaurInfo = IndexMap<String, Package>;
 type PacmanDependencies = IndexSet<String>;
-type DepthMap = IndexMap<String, i32>;
-type RecursiveInfo = (RaurInfo, PacmanDependencies, DepthMap);
+type RecursiveInfo = (RaurInfo, PacmanDependencies, DependencyGraph);
 
 const BATCH_SIZE: usize = 200;
 
+/// Comparison operator of a dependency version constraint, as in `foo>=2.0`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VersionOperator {
+	Less,
+	LessOrEqual,
+	Equal,
+	GreaterOrEqual,
+	Greater,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VersionConstraint {
+	pub operator: VersionOperator,
+	pub version: String,
+}
+
+impl VersionConstraint {
+	/// Checks the version against this constraint, comparing versions like `vercmp` does
+	pub fn is_satisfied_by(&self, version: &str, alpm: &dyn AlpmWrapper) -> Result<bool> {
+		let ordering = alpm.version_compare(version, &self.version)?;
+		Ok(match self.operator {
+			VersionOperator::Less => ordering == Ordering::Less,
+			VersionOperator::LessOrEqual => ordering != Ordering::Greater,
+			VersionOperator::Equal => ordering == Ordering::Equal,
+			VersionOperator::GreaterOrEqual => ordering != Ordering::Less,
+			VersionOperator::Greater => ordering == Ordering::Greater,
+		})
+	}
+}
+
+impl fmt::Display for VersionConstraint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let operator = match self.operator {
+			VersionOperator::Less => "<",
+			VersionOperator::LessOrEqual => "<=",
+			VersionOperator::Equal => "=",
+			VersionOperator::GreaterOrEqual => ">=",
+			VersionOperator::Greater => ">",
+		};
+		write!(f, "{}{}", operator, self.version)
+	}
+}
+
+/// A dependency as written in PKGBUILD, for example `foo` or `foo>=2.0`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Dependency {
+	pub name: String,
+	pub constraint: Option<VersionConstraint>,
+}
+
+impl fmt::Display for Dependency {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.constraint {
+			Some(constraint) => write!(f, "{}{}", self.name, constraint),
+			None => write!(f, "{}", self.name),
+		}
+	}
+}
+
+/// Where a dependency is going to come from
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Resolution {
+	/// Already installed in a satisfying version
+	Installed,
+	/// Installable (or upgradable) from pacman repositories
+	Repo,
+	/// Needs to be built from AUR
+	Aur,
+}
+
+/// Resolves all the dependencies at once, querying pacman in batches instead of once per dependency.
+/// Resolutions are returned in the order of `dependencies`.
+fn resolve_dependencies(
+	dependencies: &[&Dependency],
+	alpm: &dyn AlpmWrapper,
+) -> Result<Vec<Resolution>> {
+	let names = dependencies
+		.iter()
+		.map(|dependency| dependency.name.to_string())
+		.unique()
+		.collect_vec();
+	let constrained = dependencies
+		.iter()
+		.filter(|dependency| dependency.constraint.is_some())
+		.map(|dependency| dependency.name.to_string())
+		.unique()
+		.collect_vec();
+	let installed = alpm.installed_subset(&names)?;
+	// constrained dependencies might need an upgrade from repositories, even if installed
+	let maybe_installable = names
+		.iter()
+		.filter(|name| !installed.contains(*name) || constrained.contains(name))
+		.cloned()
+		.collect_vec();
+	let installable = alpm.installable_subset(&maybe_installable)?;
+	// Querying by name shows which version is there, querying with the constraint
+	// finds a satisfying package among several providing the name
+	let constrained_dependencies = |subset: &HashSet<String>| {
+		dependencies
+			.iter()
+			.filter(|dependency| dependency.constraint.is_some())
+			.filter(|dependency| subset.contains(&dependency.name))
+			.flat_map(|dependency| [dependency.name.to_string(), dependency.to_string()])
+			.unique()
+			.collect_vec()
+	};
+	let installed_providers = alpm.installed_providers(&constrained_dependencies(&installed))?;
+	let repo_providers = alpm.repo_providers(&constrained_dependencies(&installable))?;
+	let mut result = Vec::with_capacity(dependencies.len());
+	for dependency in dependencies {
+		let name = &dependency.name;
+		if dependency.constraint.is_none() {
+			result.push(if installed.contains(name) {
+				Resolution::Installed
+			} else if installable.contains(name) {
+				Resolution::Repo
+			} else {
+				Resolution::Aur
+			});
+			continue;
+		}
+		if installed.contains(name) {
+			if has_satisfying_provider(dependency, &installed_providers, alpm)? {
+				result.push(Resolution::Installed);
+				continue;
+			}
+			eprintln!(
+				"Installed {} does not satisfy {}, it needs to be upgraded",
+				describe_providers(name, &installed_providers),
+				dependency
+			);
+		}
+		let resolution = if !installable.contains(name) {
+			Resolution::Aur
+		} else if has_satisfying_provider(dependency, &repo_providers, alpm)? {
+			Resolution::Repo
+		} else {
+			eprintln!(
+				"Repository {} does not satisfy {}, looking in AUR",
+				describe_providers(name, &repo_providers),
+				dependency
+			);
+			Resolution::Aur
+		};
+		result.push(resolution);
+	}
+	Ok(result)
+}
+
+/// Checks if the package satisfies the dependency, either by its own name and version,
+/// or through a `provides` entry like `java-runtime=11`.
+/// As in pacman, a `provides` entry without a version does not satisfy a version constraint.
+fn satisfies(
+	dependency: &Dependency,
+	name: &str,
+	version: &str,
+	provides: &[String],
+	alpm: &dyn AlpmWrapper,
+) -> Result<bool> {
+	let constraint = match &dependency.constraint {
+		Some(constraint) => constraint,
+		None => {
+			let provided = provides.iter().filter_map(|p| parse_dependency(p));
+			return Ok(
+				name == dependency.name || provided.map(|p| p.name).any(|p| p == dependency.name)
+			);
+		}
+	};
+	if name == dependency.name && constraint.is_satisfied_by(version, alpm)? {
+		return Ok(true);
+	}
+	for provided in provides.iter().filter_map(|p| parse_dependency(p)) {
+		if provided.name != dependency.name {
+			continue;
+		}
+		if let Some(VersionConstraint {
+			operator: VersionOperator::Equal,
+			version,
+		}) = provided.constraint
+		{
+			if constraint.is_satisfied_by(&version, alpm)? {
+				return Ok(true);
+			}
+		}
+	}
+	Ok(false)
+}
+
+fn has_satisfying_provider(
+	dependency: &Dependency,
+	providers: &[Provider],
+	alpm: &dyn AlpmWrapper,
+) -> Result<bool> {
+	for provider in providers {
+		if satisfies(
+			dependency,
+			&provider.name,
+			&provider.version,
+			&provider.provides,
+			alpm,
+		)? {
+			return Ok(true);
+		}
+	}
+	Ok(false)
+}
+
+/// Describes the packages that have or provide `name`, like `jre8 8.1-1 (java-runtime=8)`
+fn describe_providers(name: &str, providers: &[Provider]) -> String {
+	let described = providers.iter().filter_map(|provider| {
+		let provided = provider
+			.provides
+			.iter()
+			.filter(|p| parse_dependency(p).map_or(false, |p| p.name == name))
+			.collect_vec();
+		if provider.name == name {
+			Some(format!("{} {}", provider.name, provider.version))
+		} else if provided.is_empty() {
+			None
+		} else {
+			Some(format!(
+				"{} {} ({})",
+				provider.name,
+				provider.version,
+				provided.iter().format(", ")
+			))
+		}
+	});
+	described.unique().join(", ")
+}
+
 pub fn recursive_info(
 	root_packages_to_process: &[String],
 	alpm: &dyn AlpmWrapper,
+	aur: &dyn AurWrapper,
+	noconfirm: Option<&NoConfirm>,
 ) -> Result<RecursiveInfo> {
-	let raur_handle = Handle::default();
 	let mut queue: Vec<String> = Vec::from(root_packages_to_process);
-	let mut depth_map = IndexMap::new();
+	let mut graph = DependencyGraph::default();
 	for pkg in &queue {
-		depth_map.insert(pkg.to_string(), 0);
+		graph.add_root(pkg);
 	}
 	let mut pacman_deps: IndexSet<String> = IndexSet::new();
 	let mut info_map: IndexMap<String, Package> = IndexMap::new();
+	// (required by, dependency) for AUR dependencies with version constraints
+	let mut constrained_aur_deps: Vec<(String, Dependency)> = Vec::new();
 	while !queue.is_empty() {
 		let split_at = queue.len().max(BATCH_SIZE) - BATCH_SIZE;
 		let to_process = queue.split_off(split_at);
 		trace!("to_process: {:?}", to_process);
-		for info in raur_handle.info(&to_process)? {
-			let make_deps = info.make_depends.iter();
-			let check_deps = info.check_depends.iter();
-			let flat_deps = info.depends.iter();
-			let deps = make_deps
-				.chain(flat_deps)
-				.chain(check_deps)
-				.map(|d| clean_and_check_package_name(d))
-				.collect_vec();
-
-			for dependency in deps.into_iter() {
-				if alpm.is_installed(&dependency)? {
-					// skip if already installed
-				} else if !alpm.is_installable(&dependency)? {
-					if !depth_map.contains_key(&dependency) {
-						eprintln!(
-							"Package {} depends on {}. Resolving...",
-							info.name, dependency
-						);
-						queue.push(dependency.to_string())
-					} else {
-						eprintln!("Skipping already resolved dependency {}", dependency);
+		let infos = aur.info(&to_process)?;
+		let not_found = to_process
+			.into_iter()
+			.filter(|name| infos.iter().all(|info| &info.name != name))
+			.collect_vec();
+		let deps_per_package = infos
+			.iter()
+			.map(|info| {
+				let make_deps = info
+					.make_depends
+					.iter()
+					.map(|d| (d, DependencyKind::MakeDepends));
+				let check_deps = info
+					.check_depends
+					.iter()
+					.map(|d| (d, DependencyKind::CheckDepends));
+				let flat_deps = info.depends.iter().map(|d| (d, DependencyKind::Depends));
+				make_deps
+					.chain(flat_deps)
+					.chain(check_deps)
+					.map(|(d, kind)| (parse_and_check_dependency(d), kind))
+					.collect_vec()
+			})
+			.collect_vec();
+		let all_deps = deps_per_package
+			.iter()
+			.flatten()
+			.map(|(dependency, _)| dependency);
+		let mut resolutions = resolve_dependencies(&all_deps.collect_vec(), alpm)?.into_iter();
+		for (info, deps) in infos.into_iter().zip(deps_per_package) {
+			graph.set_pkgbase(&info.name, &info.package_base);
+			for (dependency, kind) in deps.into_iter() {
+				let name = &dependency.name;
+				let resolution = resolutions
+					.next()
+					.expect("Internal error: a dependency was not resolved");
+				match resolution {
+					Resolution::Installed => graph.add_node(name, NodeKind::Installed),
+					Resolution::Repo => {
+						graph.add_node(name, NodeKind::Repo);
+						// with the constraint, pacman picks a satisfying package among the providers
+						pacman_deps.insert(dependency.to_string());
 					}
-					let parent_depth = depth_map
-						.get(&info.name)
-						.expect("Internal error: queue element does not have depth");
-					let new_depth = depth_map
-						.get(&dependency)
-						.map_or(parent_depth + 1, |d| (*d).max(parent_depth + 1));
-					depth_map.insert(dependency.to_string(), new_depth);
-				} else {
-					pacman_deps.insert(dependency.to_owned());
+					Resolution::Aur => {
+						if graph.node_kind(name) != Some(NodeKind::Aur) {
+							eprintln!("Package {} depends on {}. Resolving...", info.name, name);
+							queue.push(name.to_string())
+						} else {
+							eprintln!("Skipping already resolved dependency {}", name);
+						}
+						graph.add_node(name, NodeKind::Aur);
+					}
+				}
+				graph.add_edge(&info.name, name, kind);
+				if resolution == Resolution::Aur && dependency.constraint.is_some() {
+					constrained_aur_deps.push((info.name.to_string(), dependency));
 				}
 			}
 			info_map.insert(info.name.to_string(), info);
 		}
+		for name in not_found {
+			let dependencies = constrained_aur_deps
+				.iter()
+				.map(|(_required_by, dependency)| dependency)
+				.filter(|dependency| dependency.name == name)
+				.collect_vec();
+			if let Some(provider) =
+				find_provider(&name, &dependencies, &graph, aur, alpm, noconfirm)?
+			{
+				eprintln!("{} is provided by AUR package {}", name, provider);
+				if graph.node_kind(&provider) != Some(NodeKind::Aur) {
+					queue.push(provider.to_string());
+				}
+				graph.replace(&name, &provider);
+			}
+		}
+	}
+	for (required_by, dependency) in constrained_aur_deps {
+		let constraint = dependency
+			.constraint
+			.as_ref()
+			.expect("Internal error: unconstrained dependency in constraint check");
+		if let Some(info) = info_map.get(&dependency.name) {
+			if !constraint.is_satisfied_by(&info.version, alpm)? {
+				eprintln!(
+					"{}: {} requires {}, but AUR has version {}",
+					"WARNING".yellow(),
+					required_by,
+					dependency,
+					info.version
+				);
+			}
+		}
+	}
+	Ok((info_map, pacman_deps, graph))
+}
+
+/// Finds an AUR package providing `name`, for packages like `java-runtime` or `libfoo.so`.
+/// The provided version must satisfy the version constraints of `dependencies` on `name`.
+/// If there are several candidates, the one already being resolved is preferred,
+/// otherwise the user is asked to choose.
+fn find_provider(
+	name: &str,
+	dependencies: &[&Dependency],
+	graph: &DependencyGraph,
+	aur: &dyn AurWrapper,
+	alpm: &dyn AlpmWrapper,
+	noconfirm: Option<&NoConfirm>,
+) -> Result<Option<String>> {
+	let mut candidates = Vec::new();
+	let mut unsatisfying = Vec::new();
+	'candidates: for candidate in aur.search_by(name, SearchBy::Provides)? {
+		if candidate.name == name {
+			continue;
+		}
+		for dependency in dependencies {
+			let provides = &candidate.provides;
+			if !satisfies(
+				dependency,
+				&candidate.name,
+				&candidate.version,
+				provides,
+				alpm,
+			)? {
+				unsatisfying.push(candidate.name);
+				continue 'candidates;
+			}
+		}
+		candidates.push(candidate);
+	}
+	if candidates.is_empty() && !unsatisfying.is_empty() {
+		eprintln!(
+			"{}: AUR packages {} provide {}, but not in a version satisfying {}",
+			"WARNING".yellow(),
+			unsatisfying.iter().format(", "),
+			name,
+			dependencies.iter().format(", ")
+		);
+	}
+	candidates.sort_by(|a, b| b.popularity.total_cmp(&a.popularity));
+	if candidates.len() <= 1 {
+		return Ok(candidates.pop().map(|candidate| candidate.name));
+	}
+	if let Some(candidate) = candidates
+		.iter()
+		.find(|candidate| graph.node_kind(&candidate.name) == Some(NodeKind::Aur))
+	{
+		return Ok(Some(candidate.name.to_string()));
+	}
+	if noconfirm.is_some() {
+		let candidates = candidates.iter().map(|candidate| &candidate.name);
+		noconfirm::reject(
+			noconfirm::EXIT_CHOICE_REQUIRED,
+			&format!(
+				"{} is provided by several AUR packages: {}. Add the one you want to the targets",
+				name,
+				candidates.format(", ")
+			),
+		);
+	}
+	Ok(Some(choose_provider(name, &candidates)))
+}
+
+fn choose_provider(name: &str, candidates: &[Package]) -> String {
+	eprintln!(
+		"\nThere are {} packages providing {}:",
+		candidates.len(),
+		name
+	);
+	for (index, candidate) in candidates.iter().enumerate() {
+		eprintln!(
+			"  {}) {} {}  {}",
+			index + 1,
+			candidate.name.yellow(),
+			candidate.version.green(),
+			candidate.description.as_deref().unwrap_or("").dimmed()
+		);
+	}
+	loop {
+		eprint!("Choose the package to use [1-{}]: ", candidates.len());
+		let user_input = terminal_util::read_line_lowercase();
+		match user_input.parse::<usize>() {
+			Ok(number) if (1..=candidates.len()).contains(&number) => {
+				return candidates[number - 1].name.to_string();
+			}
+			_ => {}
+		}
 	}
-	Ok((info_map, pacman_deps, depth_map))
 }
 
 /// Queries the AUR for the provided given package names and returns a map of all packages
@@ -78,11 +472,15 @@ pub fn recursive_info(
 ///
 /// # Arguments
 /// * `packages_to_query` - A slice of package names to find in the AUR
-pub fn info_map<S: AsRef<str>>(packages_to_query: &[S]) -> Result<IndexMap<String, Package>> {
-	let raur_handle = Handle::new();
+/// * `aur` - AUR backend to query
+pub fn info_map<S: AsRef<str>>(
+	packages_to_query: &[S],
+	aur: &dyn AurWrapper,
+) -> Result<IndexMap<String, Package>> {
 	let mut result = IndexMap::new();
 	for group in packages_to_query.chunks(BATCH_SIZE) {
-		let group_info = raur_handle.info(group)?;
+		let group = group.iter().map(|p| p.as_ref().to_string()).collect_vec();
+		let group_info = aur.info(&group)?;
 		for pkg in group_info.into_iter() {
 			result.insert(pkg.name.to_string(), pkg);
 		}
@@ -90,26 +488,24 @@ pub fn info_map<S: AsRef<str>>(packages_to_query: &[S]) -> Result<IndexMap<Strin
 	Ok(result)
 }
 
-fn clean_and_check_package_name(name: &str) -> String {
-	match clean_package_name(name) {
-		Some(name) => name,
+fn parse_and_check_dependency(dependency: &str) -> Dependency {
+	match parse_dependency(dependency) {
+		Some(dependency) => dependency,
 		None => {
-			eprintln!("Unexpected package name {}", name);
+			eprintln!("Unexpected package name {}", dependency);
 			std::process::exit(1)
 		}
 	}
 }
 
-fn clean_package_name(name: &str) -> Option<String> {
-	lazy_static! {
-		static ref CLEANUP: Regex = Regex::new(r"(=.*|>.*|<.*)").unwrap_or_else(|err| panic!(
-			"{}:{} Failed to parse regexp, {}",
-			file!(),
-			line!(),
-			err
-		));
-	}
-	let name: String = CLEANUP.replace_all(name, "").to_string();
+fn parse_dependency(dependency: &str) -> Option<Dependency> {
+	let (name, constraint) = match dependency.find(['<', '>', '=']) {
+		None => (dependency, None),
+		Some(pos) => {
+			let (name, constraint) = dependency.split_at(pos);
+			(name, Some(parse_constraint(constraint)?))
+		}
+	};
 	lazy_static! {
 		// From PKGBUILD manual page:
 		// Valid characters are alphanumerics, and any of the following characters: “@ . _ + -”.
@@ -118,16 +514,129 @@ fn clean_package_name(name: &str) -> Option<String> {
 			|err| panic!("{}:{} Failed to parse regexp, {}", file!(), line!(), err)
 		);
 	}
-	if NAME_REGEX.is_match(&name) {
-		Some(name)
+	if NAME_REGEX.is_match(name) {
+		Some(Dependency {
+			name: name.to_string(),
+			constraint,
+		})
 	} else {
 		None
 	}
 }
 
+fn parse_constraint(constraint: &str) -> Option<VersionConstraint> {
+	// longer operators go first, so that `>=` is not parsed as `>`
+	let operators = [
+		("<=", VersionOperator::LessOrEqual),
+		(">=", VersionOperator::GreaterOrEqual),
+		("==", VersionOperator::Equal),
+		("<", VersionOperator::Less),
+		(">", VersionOperator::Greater),
+		("=", VersionOperator::Equal),
+	];
+	let (version, operator) = operators.iter().find_map(|(prefix, operator)| {
+		constraint
+			.strip_prefix(prefix)
+			.map(|version| (version, *operator))
+	})?;
+	if version.is_empty() {
+		None
+	} else {
+		Some(VersionConstraint {
+			operator,
+			version: version.to_string(),
+		})
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::aur_rpc_utils::*;
+	use crate::fake_wrappers::FakeAlpm;
+	use crate::fake_wrappers::FakeAur;
+
+	fn resolve(targets: &[&str]) -> RecursiveInfo {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let aur = FakeAur::from_fixture("aur.json");
+		let targets = targets.iter().map(|t| t.to_string()).collect_vec();
+		recursive_info(&targets, &alpm, &aur, None).unwrap()
+	}
+
+	#[test]
+	fn test_recursive_info() {
+		let (info, pacman_deps, graph) = resolve(&["app"]);
+		assert_eq!(
+			info.keys().sorted().collect_vec(),
+			["app", "liba", "libb", "libtest-helper"]
+		);
+		assert_eq!(
+			pacman_deps.iter().sorted().collect_vec(),
+			["cmake", "python3"]
+		);
+		let edges = |name| {
+			let edges = graph.dependencies(name);
+			edges
+				.map(|edge| (edge.to.as_str(), edge.kind))
+				.collect_vec()
+		};
+		use DependencyKind::*;
+		assert_eq!(
+			edges("app"),
+			[
+				("cmake", MakeDepends),
+				("liba", Depends),
+				("glibc", Depends),
+				("python3", Depends)
+			]
+		);
+		assert_eq!(
+			edges("liba"),
+			[
+				("libb", Depends),
+				("sh", Depends),
+				("libtest-helper", CheckDepends)
+			]
+		);
+		assert_eq!(graph.node_kind("app"), Some(NodeKind::Aur));
+		assert_eq!(graph.node_kind("cmake"), Some(NodeKind::Repo));
+		assert_eq!(graph.node_kind("sh"), Some(NodeKind::Installed));
+		assert_eq!(graph.roots().iter().collect_vec(), ["app"]);
+	}
+
+	#[test]
+	fn test_recursive_info_split_packages() {
+		let (_, pacman_deps, graph) = resolve(&["libb", "split-two"]);
+		assert!(pacman_deps.is_empty());
+		assert_eq!(graph.pkgbase("split-one"), Some("split"));
+		assert_eq!(graph.pkgbase("split-two"), Some("split"));
+		assert_eq!(graph.build_order().unwrap(), [["libb"], ["split"]]);
+	}
+
+	#[test]
+	fn test_recursive_info_reports_cycle() {
+		let (_, _, graph) = resolve(&["cycle-a"]);
+		let cycle = graph.build_order().unwrap_err();
+		assert_eq!(
+			cycle.to_string(),
+			"cycle-a -[depends]-> cycle-b -[makedepends]-> cycle-a"
+		);
+	}
+
+	#[test]
+	fn test_recursive_info_reports_unknown_dependency() {
+		let (info, _, graph) = resolve(&["needs-missing"]);
+		assert!(!info.contains_key("not-anywhere"));
+		assert_eq!(graph.not_found(), ["not-anywhere"]);
+	}
+
+	fn clean_package_name(name: &str) -> Option<String> {
+		parse_dependency(name).map(|dependency| dependency.name)
+	}
+
+	fn constraint(dependency: &str) -> Option<(VersionOperator, String)> {
+		let constraint = parse_dependency(dependency).unwrap().constraint?;
+		Some((constraint.operator, constraint.version))
+	}
 
 	#[test]
 	fn test_clean_package_name() {
@@ -154,4 +663,96 @@ mod tests {
 		assert_eq!(clean_package_name("test=0"), Some("test".to_string()));
 		assert_eq!(clean_package_name("test==0"), Some("test".to_string()));
 	}
+
+	#[test]
+	fn test_parse_dependency_constraint() {
+		use VersionOperator::*;
+		assert_eq!(constraint("test"), None);
+		assert_eq!(
+			constraint("test>=1.0"),
+			Some((GreaterOrEqual, "1.0".into()))
+		);
+		assert_eq!(
+			constraint("test>1:1.0-2"),
+			Some((Greater, "1:1.0-2".into()))
+		);
+		assert_eq!(constraint("test<1.0"), Some((Less, "1.0".into())));
+		assert_eq!(constraint("test<=1.0"), Some((LessOrEqual, "1.0".into())));
+		assert_eq!(constraint("test=1.0"), Some((Equal, "1.0".into())));
+		assert_eq!(constraint("test==1.0"), Some((Equal, "1.0".into())));
+		assert_eq!(parse_dependency("test>="), None);
+		assert_eq!(parse_dependency(">=1.0"), None);
+	}
+
+	#[test]
+	fn test_version_constraint() {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let satisfied = |dependency: &str, version: &str| {
+			let dependency = parse_dependency(dependency).unwrap();
+			let constraint = dependency.constraint.unwrap();
+			constraint.is_satisfied_by(version, &alpm).unwrap()
+		};
+		assert!(satisfied("foo>=1.0", "1.0-1"));
+		assert!(satisfied("foo>=1.0", "1.1-1"));
+		assert!(!satisfied("foo>=1.0", "0.9-1"));
+		assert!(satisfied("foo=1.0", "1.0-3"));
+		assert!(!satisfied("foo=1.0-2", "1.0-3"));
+		assert!(satisfied("foo<2", "1.9"));
+		assert!(!satisfied("foo<2", "2"));
+		assert!(satisfied("foo<=2", "2"));
+		assert!(!satisfied("foo>2", "2"));
+	}
+
+	#[test]
+	fn test_recursive_info_provides() {
+		let (info, _, graph) = resolve(&["needs-virtual", "virtual-runtime"]);
+		assert_eq!(graph.node_kind("virtual-runtime"), None);
+		assert_eq!(
+			graph.roots().iter().collect_vec(),
+			["needs-virtual", "runtime-bin"]
+		);
+		assert_eq!(
+			graph.build_order().unwrap(),
+			[["runtime-bin"], ["needs-virtual"]]
+		);
+		assert!(info.contains_key("runtime-bin"));
+		assert!(!info.contains_key("runtime-alt"));
+	}
+
+	#[test]
+	fn test_recursive_info_prefers_provider_being_resolved() {
+		let (info, _, graph) = resolve(&["needs-runtime-bin"]);
+		assert_eq!(graph.node_kind("libruntime.so"), None);
+		assert!(info.contains_key("runtime-bin"));
+		assert!(!info.contains_key("runtime-alt"));
+	}
+
+	#[test]
+	fn test_recursive_info_version_constraints() {
+		let (info, pacman_deps, graph) = resolve(&["constrained"]);
+		// installed libold 1.0-1 is too old, AUR has 1.2-1
+		assert!(info.contains_key("libold"));
+		assert_eq!(graph.node_kind("libold"), Some(NodeKind::Aur));
+		// installed zlib 1.2 is too old, repo has 1.3
+		assert_eq!(
+			pacman_deps.iter().sorted().collect_vec(),
+			["cmake<4", "zlib>=1:1.3"]
+		);
+		assert_eq!(graph.node_kind("glibc"), Some(NodeKind::Installed));
+	}
+
+	#[test]
+	fn test_recursive_info_versioned_provides() {
+		// installed jre8 provides java-runtime=8, repo jre17 provides java-runtime=17;
+		// bash provides sh without a version, which cannot satisfy sh>=1
+		let (info, pacman_deps, graph) = resolve(&["needs-java"]);
+		assert_eq!(graph.node_kind("java-runtime"), Some(NodeKind::Repo));
+		assert_eq!(pacman_deps.iter().collect_vec(), ["java-runtime>=11"]);
+		assert_eq!(graph.node_kind("sh"), Some(NodeKind::Aur));
+		assert!(!info.contains_key("sh"));
+		// runtime-bin provides virtual-runtime=8 only
+		let (info, _, graph) = resolve(&["needs-newer-virtual"]);
+		assert!(!info.contains_key("runtime-bin"));
+		assert_eq!(graph.not_found(), ["virtual-runtime"]);
+	}
 }
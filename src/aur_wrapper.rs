@@ -0,0 +1,35 @@
+use crate::rua_environment;
+use anyhow::Result;
+use raur::blocking::Handle;
+use raur::blocking::Raur;
+use raur::Package;
+use raur::SearchBy;
+
+pub fn new_aur_wrapper() -> Box<dyn AurWrapper> {
+	Box::new(AurRpcWrapper {
+		handle: Handle::new_with_url(format!("{}/rpc/", rua_environment::aur_url())),
+	})
+}
+
+pub trait AurWrapper {
+	/// Returns information about packages with exactly these names.
+	/// Packages that do not exist on AUR are silently skipped.
+	fn info(&self, packages: &[String]) -> Result<Vec<Package>>;
+
+	/// Searches AUR by the given strategy, see `raur::SearchBy`
+	fn search_by(&self, query: &str, strategy: SearchBy) -> Result<Vec<Package>>;
+}
+
+struct AurRpcWrapper {
+	handle: Handle,
+}
+
+impl AurWrapper for AurRpcWrapper {
+	fn info(&self, packages: &[String]) -> Result<Vec<Package>> {
+		Ok(self.handle.info(packages)?)
+	}
+
+	fn search_by(&self, query: &str, strategy: SearchBy) -> Result<Vec<Package>> {
+		Ok(self.handle.search_by(query, strategy)?)
+	}
+}
@@ -0,0 +1,82 @@
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use itertools::Itertools;
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// File names are `<timestamp>_<version>.log`, so that sorting by name sorts by time
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S";
+
+/// makepkg output of one build, kept in the logs directory of its pkgbase
+pub struct BuildLog {
+	pub path: PathBuf,
+	pub file: File,
+}
+
+impl BuildLog {
+	pub fn create(pkgbase_logs_dir: &Path, version: &str) -> BuildLog {
+		fs::create_dir_all(pkgbase_logs_dir).unwrap_or_else(|err| {
+			panic!(
+				"Failed to create build logs directory {:?}, {}",
+				pkgbase_logs_dir, err
+			)
+		});
+		let timestamp = Utc::now().format(TIMESTAMP_FORMAT);
+		let path = pkgbase_logs_dir.join(format!("{}_{}.log", timestamp, version));
+		let file = File::create(&path)
+			.unwrap_or_else(|err| panic!("Failed to create build log {:?}, {}", path, err));
+		BuildLog { path, file }
+	}
+}
+
+/// A build log found on disk
+#[derive(Debug, PartialEq, Eq)]
+pub struct LogEntry {
+	pub path: PathBuf,
+	/// Build start time, UTC
+	pub started: NaiveDateTime,
+	pub version: String,
+}
+
+/// Build logs of a pkgbase, latest first
+pub fn list_logs(pkgbase_logs_dir: &Path) -> Vec<LogEntry> {
+	let dir = match fs::read_dir(pkgbase_logs_dir) {
+		Ok(dir) => dir,
+		Err(_) => return Vec::new(),
+	};
+	dir.filter_map(|entry| entry.ok())
+		.filter_map(|entry| {
+			let file_name = entry.file_name();
+			let (started, version) = parse_log_name(file_name.to_str()?)?;
+			Some(LogEntry {
+				path: entry.path(),
+				started,
+				version,
+			})
+		})
+		.sorted_by(|a, b| b.started.cmp(&a.started))
+		.collect()
+}
+
+fn parse_log_name(file_name: &str) -> Option<(NaiveDateTime, String)> {
+	let name = file_name.strip_suffix(".log")?;
+	let (timestamp, version) = name.split_once('_')?;
+	let started = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+	Some((started, version.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::build_log::*;
+
+	#[test]
+	fn test_parse_log_name() {
+		let (started, version) = parse_log_name("2026-10-18T07-05-09_1:2.0_rc1-3.log").unwrap();
+		assert_eq!(started.to_string(), "2026-10-18 07:05:09");
+		assert_eq!(version, "1:2.0_rc1-3");
+		assert_eq!(parse_log_name("2026-10-18T07-05-09_1.0-1.txt"), None);
+		assert_eq!(parse_log_name("random.log"), None);
+	}
+}
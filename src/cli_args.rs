@@ -1,4 +1,5 @@
 pub use cli_color_type_mod::CLIColorType;
+pub use deptree_format_mod::DeptreeFormat;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -17,10 +18,23 @@ pub mod cli_color_type_mod {
 	}
 }
 
+pub mod deptree_format_mod {
+	#![allow(clippy::useless_vec)]
+	use structopt::clap::arg_enum;
+
+	arg_enum! {
+		#[allow(non_camel_case_types)]
+		#[derive(Debug, Clone, Copy)]
+		pub enum DeptreeFormat {
+			plain, dot, json
+		}
+	}
+}
+
 #[derive(StructOpt, Debug)]
 #[structopt(
 	rename_all = "kebab-case",
-	after_help = "ENVIRONMENT:\n    RUA_SUDO_COMMAND: Sets the alternative command for sudo, such as gosu, doas, runas, suex etc."
+	after_help = "ENVIRONMENT:\n    RUA_SUDO_COMMAND: Sets the alternative command for sudo, such as gosu, doas, runas, suex etc.\n    RUA_AUR_URL: Sets the AUR base URL, same as --aur-url"
 )]
 pub struct CliArgs {
 	#[structopt(
@@ -31,6 +45,11 @@ pub struct CliArgs {
 		help = "Set colors. Respects NO_COLOR environment and CLICOLOR specification", // the rest of the description is filled in by structopt
 	)]
 	pub color: CLIColorType,
+	#[structopt(
+		long = "aur-url",
+		help = "Use this AUR instead of https://aur.archlinux.org, for example a mirror. Used for both git repositories and the RPC interface"
+	)]
+	pub aur_url: Option<String>,
 	#[structopt(subcommand)]
 	pub action: Action,
 }
@@ -58,6 +77,27 @@ Sources are downloaded using .SRCINFO only"
 		)]
 		target: Option<PathBuf>,
 	},
+	#[structopt(
+		about = "Show the dependency tree of AUR packages, as it would be resolved for installation"
+	)]
+	Deptree {
+		#[structopt(
+			possible_values = &DeptreeFormat::variants(),
+			case_insensitive = true,
+			default_value = "plain",
+			long = "format",
+			help = "Output format"
+		)]
+		format: DeptreeFormat,
+		#[structopt(
+			long = "reverse",
+			short = "r",
+			help = "Only show what requires the given package, from that package up to the targets"
+		)]
+		reverse: Option<String>,
+		#[structopt(help = "Target package", multiple = true, required = true)]
+		target: Vec<String>,
+	},
 	#[structopt(about = "Show package information")]
 	Info {
 		#[structopt(help = "Target to show for", multiple = true, required = true)]
@@ -67,6 +107,8 @@ Sources are downloaded using .SRCINFO only"
 	Install {
 		#[structopt(long = "asdeps", help = "Install package as dependency")]
 		asdeps: bool,
+		#[structopt(flatten)]
+		build: BuildArgs,
 		#[structopt(
 			short = "o",
 			long = "offline",
@@ -74,9 +116,55 @@ Sources are downloaded using .SRCINFO only"
 Sources are downloaded using .SRCINFO only"
 		)]
 		offline: bool,
+		#[structopt(
+			long = "local-repo",
+			parse(from_os_str),
+			help = "Add built packages to this local pacman repository database, such as /srv/repo/aur.db.tar.gz, instead of installing them.
+Packages that other targets need for building are still installed, as dependencies"
+		)]
+		local_repo: Option<PathBuf>,
 		#[structopt(help = "Target package", multiple = true, required = true)]
 		target: Vec<String>,
 	},
+	#[structopt(
+		about = "Show who approved which commit of AUR packages, and when. Without a package, shows the last approval of each package"
+	)]
+	Ledger {
+		#[structopt(help = "Package base name, to show all its approvals")]
+		target: Option<String>,
+	},
+	#[structopt(
+		about = "List build logs of a package, latest first, or view one of them with $PAGER"
+	)]
+	Log {
+		#[structopt(help = "Package base name")]
+		target: String,
+		#[structopt(help = "Log number to view, as shown in the list. 1 is the latest build")]
+		number: Option<usize>,
+	},
+	#[structopt(
+		about = "Manage local patches: commits you made on top of an AUR package in the review shell"
+	)]
+	Patches {
+		#[structopt(subcommand)]
+		action: PatchesAction,
+	},
+	#[structopt(
+		about = "Fetch AUR packages and review them, without building. The review is kept for later installs and upgrades"
+	)]
+	Review {
+		#[structopt(
+			long = "all-installed",
+			help = "Review all installed packages that are not from pacman repositories"
+		)]
+		all_installed: bool,
+		#[structopt(
+			help = "Target package",
+			multiple = true,
+			required_unless = "all-installed"
+		)]
+		target: Vec<String>,
+	},
 	#[structopt(
 		about = "Search for packages by name or description. If multiple keywords are used, all of them must match."
 	)]
@@ -84,6 +172,16 @@ Sources are downloaded using .SRCINFO only"
 		#[structopt(help = "Target to search for", multiple = true, required = true)]
 		target: Vec<String>,
 	},
+	#[structopt(
+		about = "Show reviewed packages: unmerged upstream changes, local changes, installed versions and the last reviewed commit"
+	)]
+	Status {
+		#[structopt(
+			long = "fetch",
+			help = "Fetch upstream changes first. Otherwise, upstream is as of the last review or fetch"
+		)]
+		fetch: bool,
+	},
 	#[structopt(
 		about = "Run shellcheck on a PKGBUILD, taking care of PKGBUILD-specific variables"
 	)]
@@ -121,6 +219,71 @@ Supports: git, hg, bzr, svn, cvs, darcs. Currently by suffix only."
 			help = "Don't upgrade the specified package(s). Accepts multiple arguments separated by `,`."
 		)]
 		ignored: Option<String>,
+		#[structopt(flatten)]
+		build: BuildArgs,
+	},
+}
+
+// Options shared by `install` and `upgrade`. Not a doc comment, it would override their `about`
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub struct BuildArgs {
+	#[structopt(
+		long = "noconfirm",
+		help = "Do not ask questions, for use in scripts. Upstream changes are merged automatically, and the build is rejected if your local repo differs from upstream, if the built package has SUID files, file capabilities or a non-allowlisted .INSTALL script. Packages are installed with the sudo command automatically.
+Exit codes: 11=review required, 12=SUID files or file capabilities, 13=install script, 14=pacman failed, 15=choice between packages required, 16=tar_check policy violation, 17=file conflict check failed"
+	)]
+	pub noconfirm: bool,
+	#[structopt(
+		long = "allow-install-script",
+		requires = "noconfirm",
+		help = "With --noconfirm, allow .INSTALL scripts in these packages. Accepts multiple arguments separated by `,`."
+	)]
+	pub allow_install_script: Option<String>,
+	#[structopt(
+		long = "jobs",
+		short = "j",
+		default_value = "1",
+		help = "Build up to this many packages at the same time, each in its own jail. Packages are only built together if they don't depend on each other. The output of parallel builds goes to log files"
+	)]
+	pub jobs: usize,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+pub enum PatchesAction {
+	#[structopt(about = "List local patches, oldest first")]
+	List {
+		#[structopt(help = "Package base name")]
+		target: String,
+	},
+	#[structopt(about = "Save local patches as files, like `git format-patch`")]
+	Export {
+		#[structopt(help = "Package base name")]
+		target: String,
+		#[structopt(
+			long = "output",
+			short = "o",
+			parse(from_os_str),
+			help = "Directory to save the patches to. Defaults to current directory"
+		)]
+		output: Option<PathBuf>,
+	},
+	#[structopt(
+		about = "Remove a local patch. The other patches are re-applied on the same upstream commit"
+	)]
+	Drop {
+		#[structopt(help = "Package base name")]
+		target: String,
+		#[structopt(help = "Patch number, as shown by `rua patches list`")]
+		number: usize,
+	},
+	#[structopt(
+		about = "Fetch upstream and re-apply local patches on top of it, instead of merging upstream. On a conflict, the local patches are left as they were"
+	)]
+	Rebase {
+		#[structopt(help = "Package base name")]
+		target: String,
 	},
 }
 
@@ -128,3 +291,5 @@ Supports: git, hg, bzr, svn, cvs, darcs. Currently by suffix only."
 // !WARNING! If you change this, make sure the value the same as documented in CliArgs above.
 #[allow(dead_code)] // unused from inside build.rs
 pub const SUDO_ENVIRONMENT_VARIABLE_NAME: &str = "RUA_SUDO_COMMAND";
+#[allow(dead_code)] // unused from inside build.rs
+pub const AUR_URL_ENVIRONMENT_VARIABLE_NAME: &str = "RUA_AUR_URL";
@@ -0,0 +1,317 @@
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use itertools::Itertools;
+use std::fmt;
+
+/// Dependency relation, as declared in PKGBUILD
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+	Depends,
+	MakeDepends,
+	CheckDepends,
+}
+
+impl fmt::Display for DependencyKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			DependencyKind::Depends => "depends",
+			DependencyKind::MakeDepends => "makedepends",
+			DependencyKind::CheckDepends => "checkdepends",
+		})
+	}
+}
+
+/// How a package is going to be satisfied, ordered by the amount of work needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NodeKind {
+	Installed,
+	Repo,
+	Aur,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+	pub from: String,
+	pub to: String,
+	pub kind: DependencyKind,
+}
+
+/// Dependency edges that lead from a package back to itself
+#[derive(Debug, PartialEq, Eq)]
+pub struct DependencyCycle {
+	pub edges: Vec<Edge>,
+}
+
+impl fmt::Display for DependencyCycle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut previous: Option<&str> = None;
+		for edge in &self.edges {
+			match previous {
+				None => write!(f, "{}", edge.from)?,
+				Some(previous) if previous == edge.from => {}
+				Some(_) => write!(f, " (built together with {})", edge.from)?,
+			}
+			write!(f, " -[{}]-> {}", edge.kind, edge.to)?;
+			previous = Some(&edge.to);
+		}
+		Ok(())
+	}
+}
+
+/// Dependency graph of the packages being installed.
+/// Nodes are package names (split packages for AUR).
+/// AUR nodes that were found on AUR also belong to a pkgbase,
+/// which is the unit that is reviewed and built.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+	roots: IndexSet<String>,
+	nodes: IndexMap<String, NodeKind>,
+	split_to_pkgbase: IndexMap<String, String>,
+	edges: Vec<Edge>,
+}
+
+impl DependencyGraph {
+	/// Adds a package requested by the user
+	pub fn add_root(&mut self, name: &str) {
+		self.roots.insert(name.to_string());
+		self.add_node(name, NodeKind::Aur);
+	}
+
+	/// Adds a node, or upgrades the kind of an existing one if more work is needed now
+	pub fn add_node(&mut self, name: &str, kind: NodeKind) {
+		let existing = self.nodes.entry(name.to_string()).or_insert(kind);
+		*existing = (*existing).max(kind);
+	}
+
+	pub fn add_edge(&mut self, from: &str, to: &str, kind: DependencyKind) {
+		let edge = Edge {
+			from: from.to_string(),
+			to: to.to_string(),
+			kind,
+		};
+		if !self.edges.contains(&edge) {
+			self.edges.push(edge);
+		}
+	}
+
+	/// Records that the AUR package was found on AUR, belonging to `pkgbase`
+	pub fn set_pkgbase(&mut self, split: &str, pkgbase: &str) {
+		self.split_to_pkgbase
+			.insert(split.to_string(), pkgbase.to_string());
+	}
+
+	/// Replaces a name that only exists as `provides` of another package by that package
+	pub fn replace(&mut self, name: &str, provider: &str) {
+		if let Some(kind) = self.nodes.shift_remove(name) {
+			self.add_node(provider, kind);
+		}
+		if self.roots.shift_remove(name) {
+			self.roots.insert(provider.to_string());
+		}
+		let edges = std::mem::take(&mut self.edges);
+		for mut edge in edges {
+			if edge.to == name {
+				edge.to = provider.to_string();
+			}
+			self.add_edge(&edge.from, &edge.to, edge.kind);
+		}
+	}
+
+	pub fn roots(&self) -> &IndexSet<String> {
+		&self.roots
+	}
+
+	pub fn node_kind(&self, name: &str) -> Option<NodeKind> {
+		self.nodes.get(name).copied()
+	}
+
+	pub fn nodes(&self) -> impl Iterator<Item = (&str, NodeKind)> {
+		self.nodes.iter().map(|(name, kind)| (name.as_str(), *kind))
+	}
+
+	pub fn pkgbase(&self, split: &str) -> Option<&str> {
+		self.split_to_pkgbase.get(split).map(String::as_str)
+	}
+
+	/// Split packages that need to be built from the given pkgbase
+	pub fn splits(&self, pkgbase: &str) -> Vec<&str> {
+		self.split_to_pkgbase
+			.iter()
+			.filter(|(_split, base)| *base == pkgbase)
+			.map(|(split, _base)| split.as_str())
+			.collect()
+	}
+
+	/// AUR packages that were found on AUR and need to be built
+	pub fn aur_splits(&self) -> impl Iterator<Item = &str> {
+		self.split_to_pkgbase.keys().map(String::as_str)
+	}
+
+	/// Packages that need to be built from AUR, but were not found there
+	pub fn not_found(&self) -> Vec<&str> {
+		self.nodes()
+			.filter(|(name, kind)| *kind == NodeKind::Aur && self.pkgbase(name).is_none())
+			.map(|(name, _kind)| name)
+			.collect()
+	}
+
+	/// Edges from the package to the packages it depends on
+	pub fn dependencies<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Edge> {
+		self.edges.iter().filter(move |edge| edge.from == name)
+	}
+
+	/// Edges from packages depending on the given one
+	pub fn dependents<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Edge> {
+		self.edges.iter().filter(move |edge| edge.to == name)
+	}
+
+	pub fn edges(&self) -> &[Edge] {
+		&self.edges
+	}
+
+	/// Whether packages from some other pkgbase depend on this one
+	pub fn is_needed_by_others(&self, pkgbase: &str) -> bool {
+		self.splits(pkgbase).iter().any(|split| {
+			self.dependents(split)
+				.any(|edge| self.pkgbase(&edge.from) != Some(pkgbase))
+		})
+	}
+
+	/// Groups pkgbases into build stages, in topological order.
+	/// Each stage only depends on pkgbases from earlier stages,
+	/// so all pkgbases of one stage can be built and reviewed together.
+	pub fn build_order(&self) -> Result<Vec<Vec<String>>, DependencyCycle> {
+		let mut pkgbase_deps: IndexMap<&str, IndexSet<&str>> = IndexMap::new();
+		for pkgbase in self.split_to_pkgbase.values() {
+			pkgbase_deps.entry(pkgbase).or_default();
+		}
+		for edge in &self.edges {
+			if let (Some(from), Some(to)) = (self.pkgbase(&edge.from), self.pkgbase(&edge.to)) {
+				if from != to {
+					pkgbase_deps.entry(from).or_default().insert(to);
+				}
+			}
+		}
+		let mut done: IndexSet<&str> = IndexSet::new();
+		let mut stages = Vec::new();
+		while done.len() < pkgbase_deps.len() {
+			let stage = pkgbase_deps
+				.iter()
+				.filter(|(pkgbase, deps)| {
+					!done.contains(*pkgbase) && deps.iter().all(|dep| done.contains(dep))
+				})
+				.map(|(pkgbase, _deps)| *pkgbase)
+				.collect_vec();
+			if stage.is_empty() {
+				return Err(self.find_cycle(&done));
+			}
+			done.extend(stage.iter().copied());
+			stages.push(stage.into_iter().map(str::to_string).collect());
+		}
+		Ok(stages)
+	}
+
+	/// Finds a cycle among pkgbases that are not `done`.
+	/// Every such pkgbase has a dependency that is not done either,
+	/// so following these dependencies must eventually loop.
+	fn find_cycle(&self, done: &IndexSet<&str>) -> DependencyCycle {
+		let blocking_edge = |pkgbase: &str| {
+			self.edges
+				.iter()
+				.find(|edge| {
+					let from = self.pkgbase(&edge.from);
+					let to = self.pkgbase(&edge.to);
+					from == Some(pkgbase)
+						&& to.map_or(false, |to| to != pkgbase && !done.contains(to))
+				})
+				.expect("Internal error: pkgbase in a dependency cycle has no dependencies")
+		};
+		let start = self
+			.split_to_pkgbase
+			.values()
+			.find(|pkgbase| !done.contains(pkgbase.as_str()))
+			.expect("Internal error: no pkgbase left to find a cycle in");
+		let mut visited: Vec<&str> = vec![start];
+		let mut path: Vec<&Edge> = Vec::new();
+		loop {
+			let current = visited.last().expect("Internal error: empty cycle path");
+			let edge = blocking_edge(current);
+			let next = self
+				.pkgbase(&edge.to)
+				.expect("Internal error: dependency without pkgbase");
+			path.push(edge);
+			if let Some(position) = visited.iter().position(|pkgbase| *pkgbase == next) {
+				let edges = path[position..].iter().map(|edge| (*edge).clone());
+				return DependencyCycle {
+					edges: edges.collect(),
+				};
+			}
+			visited.push(next);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::dependency_graph::*;
+
+	fn graph(edges: &[(&str, &str)]) -> DependencyGraph {
+		let mut graph = DependencyGraph::default();
+		graph.add_root(edges[0].0);
+		for (from, to) in edges {
+			graph.add_node(to, NodeKind::Aur);
+			graph.add_edge(from, to, DependencyKind::Depends);
+			for split in [from, to] {
+				// "base/split" notation for split packages
+				let pkgbase = split.split('/').next().unwrap();
+				graph.set_pkgbase(split, pkgbase);
+			}
+		}
+		graph
+	}
+
+	#[test]
+	fn test_build_order() {
+		let graph = graph(&[("a", "b"), ("a", "c"), ("b", "d"), ("c", "d"), ("e", "d")]);
+		assert_eq!(
+			graph.build_order().unwrap(),
+			[vec!["d"], vec!["b", "c", "e"], vec!["a"]]
+		);
+	}
+
+	#[test]
+	fn test_build_order_split_packages() {
+		// split packages of one pkgbase are built together, even if they depend on each other
+		let graph = graph(&[("x/two", "x/one"), ("x/one", "y"), ("z", "x/two")]);
+		assert_eq!(graph.build_order().unwrap(), [["y"], ["x"], ["z"]]);
+		assert_eq!(graph.splits("x"), ["x/two", "x/one"]);
+		assert!(graph.is_needed_by_others("x"));
+		assert!(!graph.is_needed_by_others("z"));
+	}
+
+	#[test]
+	fn test_cycle() {
+		let graph = graph(&[("a", "b"), ("b", "c"), ("c", "d"), ("d", "b")]);
+		let cycle = graph.build_order().unwrap_err();
+		assert_eq!(
+			cycle.to_string(),
+			"b -[depends]-> c -[depends]-> d -[depends]-> b"
+		);
+	}
+
+	#[test]
+	fn test_cycle_through_split_packages() {
+		let graph = graph(&[("x/one", "y"), ("y", "x/two")]);
+		let cycle = graph.build_order().unwrap_err();
+		assert_eq!(cycle.to_string(), "x/one -[depends]-> y -[depends]-> x/two");
+	}
+
+	#[test]
+	fn test_replace_provided_name() {
+		let mut graph = graph(&[("a", "virtual")]);
+		graph.replace("virtual", "provider");
+		assert_eq!(graph.node_kind("virtual"), None);
+		assert_eq!(graph.node_kind("provider"), Some(NodeKind::Aur));
+		assert_eq!(graph.dependencies("a").next().unwrap().to, "provider");
+	}
+}
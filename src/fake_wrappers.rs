@@ -0,0 +1,334 @@
+//! In-memory `AlpmWrapper` and `AurWrapper` implementations for tests.
+//! They are loaded from JSON fixtures in `tests/fixtures`.
+
+use crate::alpm_wrapper::AlpmWrapper;
+use crate::alpm_wrapper::Provider;
+use crate::aur_wrapper::AurWrapper;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use raur::Package;
+use raur::SearchBy;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+fn fixture_path(name: &str) -> PathBuf {
+	Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/fixtures")
+		.join(name)
+}
+
+fn read_fixture<T: for<'de> Deserialize<'de>>(name: &str) -> Result<T> {
+	let path = fixture_path(name);
+	let content =
+		fs::read_to_string(&path).with_context(|| format!("Failed to read fixture {:?}", path))?;
+	serde_json::from_str(&content).with_context(|| format!("Failed to parse fixture {:?}", path))
+}
+
+/// Strips the version constraint from a `provides`/`depends` entry
+fn entry_name(entry: &str) -> &str {
+	entry
+		.find(['<', '>', '='])
+		.map_or(entry, |pos| &entry[..pos])
+}
+
+#[derive(Deserialize)]
+pub struct FakePackage {
+	pub name: String,
+	pub version: String,
+	#[serde(default)]
+	pub provides: Vec<String>,
+	/// Files owned by the package, relative to the root directory
+	#[serde(default)]
+	pub files: Vec<String>,
+}
+
+impl FakePackage {
+	fn satisfies(&self, package: &str) -> bool {
+		self.name == package || self.provides.iter().any(|p| entry_name(p) == package)
+	}
+
+	fn provider(&self) -> Provider {
+		Provider {
+			name: self.name.to_string(),
+			version: self.version.to_string(),
+			provides: self.provides.clone(),
+		}
+	}
+}
+
+/// Packages satisfying any of the dependencies by name. Versions are checked by the caller.
+fn providers(packages: &[FakePackage], dependencies: &[String]) -> Vec<Provider> {
+	packages
+		.iter()
+		.filter(|p| dependencies.iter().any(|d| p.satisfies(entry_name(d))))
+		.map(FakePackage::provider)
+		.collect()
+}
+
+#[derive(Deserialize)]
+pub struct FakeAlpm {
+	/// Packages installed on the fake system
+	#[serde(default)]
+	pub installed: Vec<FakePackage>,
+	/// Packages available in the fake sync repositories
+	#[serde(default)]
+	pub repo: Vec<FakePackage>,
+}
+
+impl FakeAlpm {
+	pub fn from_fixture(name: &str) -> FakeAlpm {
+		read_fixture(name).unwrap_or_else(|err| panic!("{:#}", err))
+	}
+}
+
+impl AlpmWrapper for FakeAlpm {
+	fn is_installed(&self, package: &str) -> Result<bool> {
+		Ok(self.installed.iter().any(|p| p.satisfies(package)))
+	}
+
+	fn is_installable(&self, package: &str) -> Result<bool> {
+		Ok(self.repo.iter().any(|p| p.satisfies(package)))
+	}
+
+	fn installed_version(&self, package: &str) -> Result<Option<String>> {
+		let found = self.installed.iter().find(|p| p.name == package);
+		Ok(found.map(|p| p.version.to_string()))
+	}
+
+	fn installed_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		Ok(providers(&self.installed, dependencies))
+	}
+
+	fn repo_providers(&self, dependencies: &[String]) -> Result<Vec<Provider>> {
+		Ok(providers(&self.repo, dependencies))
+	}
+
+	fn get_non_pacman_packages(&self) -> Result<Vec<(String, String)>> {
+		let result = self
+			.installed
+			.iter()
+			.filter(|installed| !self.repo.iter().any(|repo| repo.name == installed.name))
+			.map(|p| (p.name.to_string(), p.version.to_string()))
+			.collect();
+		Ok(result)
+	}
+
+	fn file_owners(&self, files: &[String]) -> Result<HashMap<String, String>> {
+		let result = self
+			.installed
+			.iter()
+			.flat_map(|p| p.files.iter().map(move |file| (file, &p.name)))
+			.filter(|(file, _name)| files.contains(file))
+			.map(|(file, name)| (file.to_string(), name.to_string()))
+			.collect();
+		Ok(result)
+	}
+
+	fn version_compare(&self, a: &str, b: &str) -> Result<Ordering> {
+		Ok(vercmp(a, b))
+	}
+}
+
+#[derive(Deserialize)]
+struct FakeAurPackage {
+	name: String,
+	/// Defaults to `name`
+	base: Option<String>,
+	version: String,
+	#[serde(default)]
+	depends: Vec<String>,
+	#[serde(default)]
+	make_depends: Vec<String>,
+	#[serde(default)]
+	check_depends: Vec<String>,
+	#[serde(default)]
+	provides: Vec<String>,
+}
+
+pub struct FakeAur {
+	pub packages: Vec<Package>,
+}
+
+impl FakeAur {
+	pub fn from_fixture(name: &str) -> FakeAur {
+		let packages: Vec<FakeAurPackage> =
+			read_fixture(name).unwrap_or_else(|err| panic!("{:#}", err));
+		let packages = packages
+			.into_iter()
+			.map(|p| Package {
+				package_base: p.base.unwrap_or_else(|| p.name.to_string()),
+				name: p.name,
+				version: p.version,
+				depends: p.depends,
+				make_depends: p.make_depends,
+				check_depends: p.check_depends,
+				provides: p.provides,
+				..Package::default()
+			})
+			.collect();
+		FakeAur { packages }
+	}
+}
+
+impl AurWrapper for FakeAur {
+	fn info(&self, packages: &[String]) -> Result<Vec<Package>> {
+		let result = self
+			.packages
+			.iter()
+			.filter(|p| packages.contains(&p.name))
+			.cloned()
+			.collect();
+		Ok(result)
+	}
+
+	fn search_by(&self, query: &str, strategy: SearchBy) -> Result<Vec<Package>> {
+		let matches = |p: &&Package| match strategy {
+			SearchBy::Name => Ok(p.name.contains(query)),
+			SearchBy::NameDesc => {
+				Ok(p.name.contains(query) || p.description.iter().any(|d| d.contains(query)))
+			}
+			SearchBy::Provides => Ok(p.name == query
+				|| p.provides
+					.iter()
+					.any(|provided| entry_name(provided) == query)),
+			_ => Err(anyhow!("Search by {} is not supported in tests", strategy)),
+		};
+		let mut result = Vec::new();
+		for package in &self.packages {
+			if matches(&package)? {
+				result.push(package.clone());
+			}
+		}
+		Ok(result)
+	}
+}
+
+/// Port of libalpm's `alpm_pkg_vercmp`, comparing `[epoch:]version[-release]`
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+	if a == b {
+		return Ordering::Equal;
+	}
+	let (epoch_a, version_a, release_a) = parse_evr(a);
+	let (epoch_b, version_b, release_b) = parse_evr(b);
+	rpmvercmp(epoch_a, epoch_b)
+		.then_with(|| rpmvercmp(version_a, version_b))
+		.then_with(|| match (release_a, release_b) {
+			(Some(release_a), Some(release_b)) => rpmvercmp(release_a, release_b),
+			_ => Ordering::Equal,
+		})
+}
+
+fn parse_evr(evr: &str) -> (&str, &str, Option<&str>) {
+	let digits = evr.bytes().take_while(u8::is_ascii_digit).count();
+	let (epoch, rest) = if evr[digits..].starts_with(':') {
+		let epoch = &evr[..digits];
+		(
+			if epoch.is_empty() { "0" } else { epoch },
+			&evr[digits + 1..],
+		)
+	} else {
+		("0", evr)
+	};
+	match rest.rfind('-') {
+		Some(pos) => (epoch, &rest[..pos], Some(&rest[pos + 1..])),
+		None => (epoch, rest, None),
+	}
+}
+
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+	if a == b {
+		return Ordering::Equal;
+	}
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	let (mut one, mut two) = (0, 0);
+	while one < a.len() && two < b.len() {
+		let separator_start = (one, two);
+		while one < a.len() && !a[one].is_ascii_alphanumeric() {
+			one += 1;
+		}
+		while two < b.len() && !b[two].is_ascii_alphanumeric() {
+			two += 1;
+		}
+		if one == a.len() || two == b.len() {
+			break;
+		}
+		// if the separator lengths differ, we are also finished
+		let separator_lengths = (one - separator_start.0, two - separator_start.1);
+		if separator_lengths.0 != separator_lengths.1 {
+			return separator_lengths.0.cmp(&separator_lengths.1);
+		}
+
+		let is_num = a[one].is_ascii_digit();
+		let segment_char = if is_num {
+			u8::is_ascii_digit
+		} else {
+			u8::is_ascii_alphabetic
+		};
+		let one_end = one + a[one..].iter().take_while(|c| segment_char(c)).count();
+		let two_end = two + b[two..].iter().take_while(|c| segment_char(c)).count();
+		if two == two_end {
+			// numeric segments are always newer than alpha segments
+			return if is_num {
+				Ordering::Greater
+			} else {
+				Ordering::Less
+			};
+		}
+		let (mut segment_a, mut segment_b) = (&a[one..one_end], &b[two..two_end]);
+		if is_num {
+			while segment_a.first() == Some(&b'0') {
+				segment_a = &segment_a[1..];
+			}
+			while segment_b.first() == Some(&b'0') {
+				segment_b = &segment_b[1..];
+			}
+			let by_length = segment_a.len().cmp(&segment_b.len());
+			if by_length != Ordering::Equal {
+				return by_length;
+			}
+		}
+		let by_content = segment_a.cmp(segment_b);
+		if by_content != Ordering::Equal {
+			return by_content;
+		}
+		one = one_end;
+		two = two_end;
+	}
+	let (rest_a, rest_b) = (&a[one..], &b[two..]);
+	if rest_a.is_empty() && rest_b.is_empty() {
+		return Ordering::Equal;
+	}
+	// A remaining alpha string never beats an empty string.
+	let first_is_alpha = |rest: &[u8]| rest.first().map_or(false, u8::is_ascii_alphabetic);
+	if (rest_a.is_empty() && !first_is_alpha(rest_b)) || first_is_alpha(rest_a) {
+		Ordering::Less
+	} else {
+		Ordering::Greater
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::fake_wrappers::vercmp;
+	use std::cmp::Ordering;
+
+	#[test]
+	fn test_vercmp() {
+		assert_eq!(vercmp("1.0", "1.0"), Ordering::Equal);
+		assert_eq!(vercmp("1.0", "1.1"), Ordering::Less);
+		assert_eq!(vercmp("1.10", "1.9"), Ordering::Greater);
+		assert_eq!(vercmp("1.0-1", "1.0-2"), Ordering::Less);
+		assert_eq!(vercmp("1.0-1", "1.0"), Ordering::Equal);
+		assert_eq!(vercmp("1:1.0", "2.0"), Ordering::Greater);
+		assert_eq!(vercmp("1.0a", "1.0"), Ordering::Less);
+		assert_eq!(vercmp("1.0.1", "1.0"), Ordering::Greater);
+		assert_eq!(vercmp("1.0alpha", "1.0beta"), Ordering::Less);
+		assert_eq!(vercmp("r10.abc", "r9.abc"), Ordering::Greater);
+		assert_eq!(vercmp("001", "1"), Ordering::Equal);
+	}
+}
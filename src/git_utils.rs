@@ -1,24 +1,57 @@
+use crate::rua_environment;
 use crate::rua_paths::RuaPaths;
 use colored::*;
 use std::path::Path;
 use std::process::Command;
 use std::process::Stdio;
 
+/// Commit of the review repository that was built last time,
+/// or a snapshot of its working tree with uncommitted changes
+pub const BUILT_REF: &str = "refs/rua/built";
+
+/// Commit of the review repository that was approved last time, see `review_ledger`.
+/// Keeps snapshots of uncommitted changes from being garbage collected.
+pub const APPROVED_REF: &str = "refs/rua/approved";
+
+/// Upstream commit that was merged before `rua patches rebase` put the local patches
+/// onto newer upstream changes. Kept until the review shows these changes, see `review_ledger`.
+pub const REVIEWED_UPSTREAM_REF: &str = "refs/rua/reviewed-upstream";
+
+/// `git hash-object -t tree /dev/null`, for diffing against nothing
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
 /// Note that we're using `git init` instead of `git clone`-like command
 /// to let the user review the initial diff.
 /// Also, the local branch does NOT track the remote one --
 /// instead it's being merged upon each review.
 pub fn init_repo(pkg: &str, dir: &Path, rua_paths: &RuaPaths) {
 	silently_run_panic_if_error(&["init", "-q"], dir, rua_paths);
-	let http_ref = format!("https://aur.archlinux.org/{}.git", pkg);
-	silently_run_panic_if_error(&["remote", "add", "upstream", &http_ref], dir, rua_paths);
-	fetch(dir, rua_paths);
+	silently_run_panic_if_error(
+		&["remote", "add", "upstream", &http_ref(pkg)],
+		dir,
+		rua_paths,
+	);
+	fetch(pkg, dir, rua_paths);
 }
 
-pub fn fetch(dir: &Path, rua_paths: &RuaPaths) {
+/// Fetches upstream. If a custom AUR URL is configured, the remote is pointed to it first,
+/// so that repositories cloned from another AUR mirror keep working.
+/// With the default AUR, a remote URL changed by the user is left as it is.
+pub fn fetch(pkg: &str, dir: &Path, rua_paths: &RuaPaths) {
+	if rua_environment::aur_url() != rua_environment::DEFAULT_AUR_URL {
+		silently_run_panic_if_error(
+			&["remote", "set-url", "upstream", &http_ref(pkg)],
+			dir,
+			rua_paths,
+		);
+	}
 	silently_run_panic_if_error(&["fetch", "-q", "upstream"], dir, rua_paths);
 }
 
+fn http_ref(pkg: &str) -> String {
+	format!("{}/{}.git", rua_environment::aur_url(), pkg)
+}
+
 pub fn is_upstream_merged(dir: &Path, rua_paths: &RuaPaths) -> bool {
 	git(dir, rua_paths)
 		.args(["merge-base", "--is-ancestor", "upstream/master", "HEAD"])
@@ -37,6 +70,112 @@ pub fn show_upstream_diff(dir: &Path, reverse: bool, rua_paths: &RuaPaths) {
 	command.arg("upstream/master").status().ok();
 }
 
+/// Remembers the working tree as the last one that was built, see `show_built_diff`.
+/// Uncommitted changes are built too, so they are kept in a snapshot, see `snapshot_working_tree`.
+pub fn mark_built(dir: &Path, rua_paths: &RuaPaths) {
+	let commit = snapshot_working_tree(dir, rua_paths);
+	update_ref(dir, BUILT_REF, &commit, rua_paths);
+}
+
+pub fn update_ref(dir: &Path, name: &str, commit: &str, rua_paths: &RuaPaths) {
+	silently_run_panic_if_error(&["update-ref", name, commit], dir, rua_paths);
+}
+
+pub fn delete_ref(dir: &Path, name: &str, rua_paths: &RuaPaths) {
+	silently_run_panic_if_error(&["update-ref", "-d", name], dir, rua_paths);
+}
+
+/// Whether local patches were rebased onto upstream changes that were not reviewed since
+pub fn has_unreviewed_rebase(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	rev_parse(dir, REVIEWED_UPSTREAM_REF, rua_paths).is_some()
+}
+
+/// Shows the upstream changes that local patches were rebased onto
+pub fn show_rebased_upstream_diff(dir: &Path, rua_paths: &RuaPaths) {
+	git(dir, rua_paths)
+		.args(["diff", REVIEWED_UPSTREAM_REF, "upstream/master"])
+		.status()
+		.ok();
+}
+
+pub fn has_built_commit(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	rev_parse(dir, BUILT_REF, rua_paths).is_some()
+}
+
+/// Shows what changed since the last build, including merged upstream changes
+pub fn show_built_diff(dir: &Path, rua_paths: &RuaPaths) {
+	git(dir, rua_paths).args(["diff", BUILT_REF]).status().ok();
+}
+
+pub fn identical_to_built(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	git(dir, rua_paths)
+		.args(["diff", "--quiet", BUILT_REF])
+		.status()
+		.map(|t| t.success())
+		.unwrap_or(false)
+}
+
+/// Unified diff without context lines, from `from` to `to` (or to the working tree).
+/// Returns an empty diff if git fails, for example if `from` does not exist.
+pub fn diff_text(
+	dir: &Path,
+	from: &str,
+	to: Option<&str>,
+	pathspec: &[&str],
+	rua_paths: &RuaPaths,
+) -> String {
+	let mut command = git(dir, rua_paths);
+	command.args(["diff", "--no-color", "--no-ext-diff", "-U0", from]);
+	command.args(to);
+	command.arg("--").args(pathspec);
+	let output = command.stderr(Stdio::null()).output();
+	match output {
+		Ok(output) if output.status.success() => {
+			String::from_utf8_lossy(&output.stdout).into_owned()
+		}
+		_ => String::new(),
+	}
+}
+
+/// Names of files that differ between `from` and `to` (or the working tree)
+pub fn changed_files(
+	dir: &Path,
+	from: &str,
+	to: Option<&str>,
+	rua_paths: &RuaPaths,
+) -> Vec<String> {
+	let mut command = git(dir, rua_paths);
+	command.args(["diff", "--name-only", from]);
+	command.args(to);
+	let output = command.stderr(Stdio::null()).output();
+	match output {
+		Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+			.lines()
+			.map(str::to_string)
+			.collect(),
+		_ => Vec::new(),
+	}
+}
+
+/// Content of the file at the given revision, or `None` if there is no such file
+pub fn show_file(dir: &Path, rev: &str, path: &str, rua_paths: &RuaPaths) -> Option<String> {
+	let output = git(dir, rua_paths)
+		.arg("show")
+		.arg(format!("{}:{}", rev, path))
+		.stderr(Stdio::null())
+		.output()
+		.ok()?;
+	if !output.status.success() {
+		return None;
+	}
+	String::from_utf8(output.stdout).ok()
+}
+
+/// Shows the working tree diff, with conflicts during a merge
+pub fn show_diff(dir: &Path, rua_paths: &RuaPaths) {
+	git(dir, rua_paths).arg("diff").status().ok();
+}
+
 pub fn identical_to_upstream(dir: &Path, rua_paths: &RuaPaths) -> bool {
 	git(dir, rua_paths)
 		.args(["diff", "--quiet", "upstream/master"])
@@ -45,19 +184,244 @@ pub fn identical_to_upstream(dir: &Path, rua_paths: &RuaPaths) -> bool {
 		.unwrap_or(false)
 }
 
-pub fn merge_upstream(dir: &Path, rua_paths: &RuaPaths) {
-	let email = "rua@local";
-	let name = "RUA";
-	git(dir, rua_paths)
-		.args(["merge", "upstream/master"])
+/// Commit with the working tree as it is, including uncommitted changes and untracked files,
+/// since that is what gets built. Returns HEAD if there are no such changes.
+/// Otherwise the commit is made on top of HEAD, but not added to any branch.
+/// It gets the date of HEAD, so that snapshots of the same files are the same commit.
+pub fn snapshot_working_tree(dir: &Path, rua_paths: &RuaPaths) -> String {
+	let head = rev_parse(dir, "HEAD", rua_paths).expect("Failed to get the HEAD commit");
+	let index = dir.join(".git").join("rua-snapshot-index");
+	let with_index = |args: &[&str]| {
+		let mut command = git(dir, rua_paths);
+		command.env("GIT_INDEX_FILE", &index).args(args);
+		output_or_panic(&mut command, args)
+	};
+	with_index(&["read-tree", "HEAD"]);
+	with_index(&["add", "-A"]);
+	let tree = with_index(&["write-tree"]);
+	std::fs::remove_file(&index)
+		.unwrap_or_else(|err| panic!("Failed to remove {:?}, {}", index, err));
+	if rev_parse(dir, "HEAD^{tree}", rua_paths).as_ref() == Some(&tree) {
+		return head;
+	}
+	let date = output_or_panic(
+		git(dir, rua_paths).args(["show", "-s", "--format=%cI", "HEAD"]),
+		&["show", "HEAD"],
+	);
+	let message = "Uncommitted changes (snapshot by RUA)";
+	let args = ["commit-tree", &tree, "-p", &head, "-m", message];
+	let mut command = git(dir, rua_paths);
+	rua_identity(&mut command)
+		.env("GIT_AUTHOR_DATE", &date)
+		.env("GIT_COMMITTER_DATE", &date)
+		.args(args);
+	output_or_panic(&mut command, &args)
+}
+
+/// Full hash of the given revision, such as `HEAD` or `HEAD^{tree}`
+pub fn rev_parse(dir: &Path, rev: &str, rua_paths: &RuaPaths) -> Option<String> {
+	let output = git(dir, rua_paths)
+		.args(["rev-parse", "--verify", "-q", rev])
+		.stderr(Stdio::null())
+		.output()
+		.ok()?;
+	let stdout = String::from_utf8(output.stdout).ok()?;
+	Some(stdout.trim().to_string()).filter(|hash| output.status.success() && !hash.is_empty())
+}
+
+/// Returns false if the merge failed, for example because of conflicts.
+/// A conflicted merge is left in progress, see `is_merging`.
+pub fn merge_upstream(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	rua_identity(git(dir, rua_paths).args(["merge", "upstream/master"]))
 		.args(["-m", "Merge branch 'upstream/master' (automated by RUA)"])
 		.arg("--no-edit")
+		.status()
+		.map(|status| status.success())
+		.unwrap_or(false)
+}
+
+pub fn is_merging(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	rev_parse(dir, "MERGE_HEAD", rua_paths).is_some()
+}
+
+pub fn abort_merge(dir: &Path, rua_paths: &RuaPaths) {
+	silently_run_panic_if_error(&["merge", "--abort"], dir, rua_paths);
+}
+
+/// Resolves conflicts in the files by taking their upstream version
+pub fn take_upstream(dir: &Path, files: &[String], rua_paths: &RuaPaths) {
+	for file in files {
+		let checked_out = git(dir, rua_paths)
+			.args(["checkout", "--theirs", "--", file])
+			.stderr(Stdio::null())
+			.status()
+			.map(|status| status.success())
+			.unwrap_or(false);
+		if checked_out {
+			silently_run_panic_if_error(&["add", "--", file], dir, rua_paths);
+		} else {
+			// deleted upstream
+			silently_run_panic_if_error(&["rm", "-q", "--", file], dir, rua_paths);
+		}
+	}
+}
+
+/// Commits the merge in progress, once all conflicts are resolved
+pub fn conclude_merge(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	rua_identity(git(dir, rua_paths).args(["commit", "-q", "--no-edit"]))
+		.status()
+		.map(|status| status.success())
+		.unwrap_or(false)
+}
+
+/// Tracked files that still contain conflict markers, such as `<<<<<<< HEAD`
+pub fn files_with_conflict_markers(dir: &Path, rua_paths: &RuaPaths) -> Vec<String> {
+	let output = git(dir, rua_paths)
+		.args(["grep", "-l", "-E", "^(<<<<<<<|>>>>>>>)( |$)"])
+		.stderr(Stdio::null())
+		.output();
+	match output {
+		Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+			.lines()
+			.map(str::to_string)
+			.collect(),
+		_ => Vec::new(),
+	}
+}
+
+fn rua_identity(command: &mut Command) -> &mut Command {
+	let email = "rua@local";
+	let name = "RUA";
+	command
 		.env("GIT_AUTHOR_NAME", name)
 		.env("GIT_AUTHOR_EMAIL", email)
 		.env("GIT_COMMITTER_NAME", name)
 		.env("GIT_COMMITTER_EMAIL", email)
+}
+
+/// Changed, untracked and ignored files of the working tree.
+/// All of them are copied to the build directory, even if `is_clean` says otherwise.
+pub fn uncommitted_files(dir: &Path, rua_paths: &RuaPaths) -> Vec<String> {
+	let output = git(dir, rua_paths)
+		.args(["status", "--porcelain", "--ignored"])
+		.output()
+		.expect("Failed to run git status");
+	assert!(
+		output.status.success(),
+		"git status failed in {:?} with exit code {:?}",
+		dir,
+		output.status.code()
+	);
+	files_from_status(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parses "XY path" lines of `git status --porcelain`
+fn files_from_status(output: &str) -> Vec<String> {
+	output
+		.lines()
+		.filter_map(|line| line.get(3..))
+		.map(str::to_string)
+		.collect()
+}
+
+/// A local commit, made on top of the upstream AUR repository
+pub struct Patch {
+	pub commit: String,
+	pub date: String,
+	pub subject: String,
+}
+
+/// Local commits, oldest first. Merges of upstream are not included.
+pub fn local_patches(dir: &Path, rua_paths: &RuaPaths) -> Vec<Patch> {
+	let output = git(dir, rua_paths)
+		.args(["log", "--no-merges", "--reverse", "--format=%H %cs %s"])
+		.arg("upstream/master..HEAD")
+		.output()
+		.unwrap_or_else(|err| panic!("Failed to list local commits in {:?}, {}", dir, err));
+	assert!(
+		output.status.success(),
+		"Failed to list local commits in {:?}: {}",
+		dir,
+		String::from_utf8_lossy(&output.stderr).red()
+	);
+	String::from_utf8_lossy(&output.stdout)
+		.lines()
+		.filter_map(|line| {
+			let (commit, rest) = line.split_once(' ')?;
+			let (date, subject) = rest.split_once(' ').unwrap_or((rest, ""));
+			Some(Patch {
+				commit: commit.to_string(),
+				date: date.to_string(),
+				subject: subject.to_string(),
+			})
+		})
+		.collect()
+}
+
+/// The commit as an email-style patch, see `git format-patch`
+pub fn format_patch(dir: &Path, commit: &str, rua_paths: &RuaPaths) -> String {
+	let args = ["format-patch", "--stdout", "-1", commit];
+	let output = git(dir, rua_paths)
+		.args(args)
+		.output()
+		.unwrap_or_else(|err| panic!("Failed to execute process git {:?}, {}", args, err));
+	assert!(
+		output.status.success(),
+		"Command git {} failed: {}",
+		args.join(" "),
+		String::from_utf8_lossy(&output.stderr).red()
+	);
+	String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn merge_base(dir: &Path, a: &str, b: &str, rua_paths: &RuaPaths) -> Option<String> {
+	let output = git(dir, rua_paths)
+		.args(["merge-base", a, b])
+		.stderr(Stdio::null())
+		.output()
+		.ok()?;
+	let stdout = String::from_utf8(output.stdout).ok()?;
+	Some(stdout.trim().to_string()).filter(|hash| output.status.success() && !hash.is_empty())
+}
+
+/// Whether there are no uncommitted changes, staged or not
+pub fn is_clean(dir: &Path, rua_paths: &RuaPaths) -> bool {
+	git(dir, rua_paths)
+		.args(["status", "--porcelain", "--untracked-files=no"])
+		.output()
+		.map(|output| output.status.success() && output.stdout.is_empty())
+		.unwrap_or(false)
+}
+
+/// Moves the current branch to `rev`, discarding uncommitted changes
+pub fn reset_hard(dir: &Path, rev: &str, rua_paths: &RuaPaths) {
+	silently_run_panic_if_error(&["reset", "-q", "--hard", rev], dir, rua_paths);
+}
+
+/// Applies the commit on top of HEAD. Returns false on conflicts, leaving them for the user.
+pub fn cherry_pick(dir: &Path, commit: &str, rua_paths: &RuaPaths) -> bool {
+	git(dir, rua_paths)
+		.args(["cherry-pick", "--allow-empty", commit])
+		.env("GIT_COMMITTER_NAME", "RUA")
+		.env("GIT_COMMITTER_EMAIL", "rua@local")
 		.status()
-		.ok();
+		.map(|status| status.success())
+		.unwrap_or(false)
+}
+
+/// Files with unresolved merge conflicts
+pub fn conflicted_files(dir: &Path, rua_paths: &RuaPaths) -> Vec<String> {
+	let output = git(dir, rua_paths)
+		.args(["diff", "--name-only", "--diff-filter=U"])
+		.stderr(Stdio::null())
+		.output();
+	match output {
+		Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+			.lines()
+			.map(str::to_string)
+			.collect(),
+		_ => Vec::new(),
+	}
 }
 
 fn silently_run_panic_if_error(args: &[&str], dir: &Path, rua_paths: &RuaPaths) {
@@ -75,6 +439,21 @@ fn silently_run_panic_if_error(args: &[&str], dir: &Path, rua_paths: &RuaPaths)
 	);
 }
 
+/// Runs git and returns its trimmed stdout
+fn output_or_panic(command: &mut Command, args: &[&str]) -> String {
+	let output = command
+		.output()
+		.unwrap_or_else(|err| panic!("Failed to execute process git {:?}, {}", args, err));
+	assert!(
+		output.status.success(),
+		"Command git {} failed with exit code {:?}\nStderr: {}",
+		args.join(" "),
+		output.status.code(),
+		String::from_utf8_lossy(&output.stderr).red(),
+	);
+	String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
 fn git(dir: &Path, rua_paths: &RuaPaths) -> Command {
 	let mut command = Command::new(&rua_paths.wrapper_bwrap_script);
 	command.arg("--bind");
@@ -86,3 +465,18 @@ fn git(dir: &Path, rua_paths: &RuaPaths) -> Command {
 	command.current_dir(dir);
 	command
 }
+
+#[cfg(test)]
+mod tests {
+	use crate::git_utils::*;
+
+	#[test]
+	fn test_files_from_status() {
+		let status = " M PKGBUILD\n?? extra-source.c\n!! src/\n";
+		assert_eq!(
+			files_from_status(status),
+			["PKGBUILD", "extra-source.c", "src/"]
+		);
+		assert!(files_from_status("").is_empty());
+	}
+}
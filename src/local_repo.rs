@@ -0,0 +1,99 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A pacman repository in a local directory, maintained with `repo-add`.
+/// See `crate::cli_args::Action::Install` for details.
+pub struct LocalRepo {
+	/// Database file, for example /srv/repo/aur.db.tar.gz
+	db_path: PathBuf,
+	/// Directory with the database and package files
+	dir: PathBuf,
+}
+
+impl LocalRepo {
+	pub fn new(db_path: &Path) -> LocalRepo {
+		let file_name = db_path.file_name().and_then(|name| name.to_str());
+		if !file_name.map_or(false, is_database_name) {
+			eprintln!(
+				"Local repository database {:?} must be named like NAME.db.tar.gz (or .db.tar.xz, .db.tar.zst, .db.tar)",
+				db_path
+			);
+			std::process::exit(1)
+		}
+		let dir = match db_path.parent() {
+			Some(parent) if parent != Path::new("") => parent.to_path_buf(),
+			_ => PathBuf::from("."),
+		};
+		fs::create_dir_all(&dir).unwrap_or_else(|err| {
+			panic!(
+				"Failed to create local repository directory {:?}, {}",
+				dir, err
+			)
+		});
+		let dir = dir.canonicalize().unwrap_or_else(|err| {
+			panic!(
+				"Cannot canonicalize local repository path {:?}, {}",
+				dir, err
+			)
+		});
+		let db_path = dir.join(
+			db_path
+				.file_name()
+				.expect("Database file name checked above"),
+		);
+		LocalRepo { db_path, dir }
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn db_path(&self) -> &Path {
+		&self.db_path
+	}
+
+	/// Adds package files that are already in the repository directory to its database
+	pub fn add(&self, packages: &[PathBuf]) {
+		if packages.is_empty() {
+			return;
+		}
+		let status = Command::new("repo-add")
+			.arg(&self.db_path)
+			.args(packages)
+			.status()
+			.unwrap_or_else(|err| panic!("Failed to run repo-add, {}", err));
+		if !status.success() {
+			eprintln!(
+				"Failed to add packages to the local repository {:?}",
+				self.db_path
+			);
+			std::process::exit(1)
+		}
+	}
+}
+
+fn is_database_name(file_name: &str) -> bool {
+	let extensions = [".db.tar", ".db.tar.gz", ".db.tar.xz", ".db.tar.zst"];
+	extensions.iter().any(|extension| {
+		file_name
+			.strip_suffix(extension)
+			.map_or(false, |name| !name.is_empty())
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::local_repo::*;
+
+	#[test]
+	fn test_is_database_name() {
+		assert!(is_database_name("aur.db.tar.gz"));
+		assert!(is_database_name("aur.db.tar.zst"));
+		assert!(is_database_name("aur.db.tar"));
+		assert!(!is_database_name(".db.tar.gz"));
+		assert!(!is_database_name("aur.db"));
+		assert!(!is_database_name("aur.files.tar.gz"));
+	}
+}
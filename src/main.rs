@@ -1,23 +1,45 @@
 mod action_builddir;
+mod action_deptree;
 mod action_install;
+mod action_ledger;
+mod action_log;
+mod action_patches;
+mod action_review;
 mod action_search;
+mod action_status;
 mod action_upgrade;
 mod alpm_wrapper;
 mod aur_rpc_utils;
+mod aur_wrapper;
+mod build_log;
 mod cli_args;
+mod dependency_graph;
+#[cfg(test)]
+mod fake_wrappers;
 mod git_utils;
+mod local_repo;
+mod noconfirm;
+mod package_metadata;
 mod pacman;
 mod print_format;
 mod print_package_info;
 mod print_package_table;
+mod review_ledger;
 mod reviewing;
 mod rua_environment;
 mod rua_paths;
+mod special_files;
+mod srcinfo_diff;
 mod srcinfo_to_pkgbuild;
+mod suspicious_patterns;
 mod tar_check;
+mod tar_check_policy;
 mod terminal_util;
 mod wrapped;
 
+use crate::action_install::InstallOptions;
+use crate::local_repo::LocalRepo;
+use crate::noconfirm::NoConfirm;
 use crate::print_package_info::info;
 use crate::wrapped::shellcheck;
 use cli_args::Action;
@@ -30,17 +52,35 @@ fn main() {
 	let cli_args: CliArgs = CliArgs::from_args();
 	rua_environment::prepare_environment(&cli_args);
 	match &cli_args.action {
+		Action::Deptree {
+			format,
+			reverse,
+			target,
+		} => action_deptree::action_deptree(target, *format, reverse.as_deref()),
 		Action::Info { ref target } => {
 			info(target, false).unwrap();
 		}
 		Action::Install {
 			asdeps,
+			build,
 			offline,
+			local_repo,
 			target,
 		} => {
 			let paths = rua_paths::RuaPaths::initialize_paths();
-			action_install::install(target, &paths, *offline, *asdeps);
+			let options = InstallOptions {
+				offline: *offline,
+				asdeps: *asdeps,
+				jobs: build.jobs,
+				noconfirm: build
+					.noconfirm
+					.then(|| NoConfirm::new(&build.allow_install_script)),
+				local_repo: local_repo.as_deref().map(LocalRepo::new),
+			};
+			action_install::install(target, &paths, &options);
 		}
+		Action::Ledger { target } => action_ledger::action_ledger(target),
+		Action::Log { target, number } => action_log::action_log(target, *number),
 		Action::Builddir {
 			offline,
 			force,
@@ -49,7 +89,22 @@ fn main() {
 			let paths = rua_paths::RuaPaths::initialize_paths();
 			action_builddir::action_builddir(target, &paths, *offline, *force);
 		}
+		Action::Patches { action } => {
+			let paths = rua_paths::RuaPaths::initialize_paths();
+			action_patches::action_patches(action, &paths);
+		}
+		Action::Review {
+			all_installed,
+			target,
+		} => {
+			let paths = rua_paths::RuaPaths::initialize_paths();
+			action_review::action_review(target, *all_installed, &paths);
+		}
 		Action::Search { target } => action_search::action_search(target),
+		Action::Status { fetch } => {
+			let paths = rua_paths::RuaPaths::initialize_paths();
+			action_status::action_status(*fetch, &paths);
+		}
 		Action::Shellcheck { target } => {
 			let result = shellcheck(target);
 			result
@@ -63,6 +118,8 @@ fn main() {
 			tar_check::tar_check_unwrap(
 				target,
 				target.to_str().expect("target is not valid UTF-8"),
+				None,
+				None,
 			);
 			eprintln!("Finished checking package: {:?}", target);
 		}
@@ -70,6 +127,7 @@ fn main() {
 			devel,
 			printonly,
 			ignored,
+			build,
 		} => {
 			let ignored_set = ignored
 				.iter()
@@ -79,7 +137,16 @@ fn main() {
 				action_upgrade::upgrade_printonly(*devel, &ignored_set);
 			} else {
 				let paths = rua_paths::RuaPaths::initialize_paths();
-				action_upgrade::upgrade_real(*devel, &paths, &ignored_set);
+				let options = InstallOptions {
+					offline: false,
+					asdeps: true,
+					jobs: build.jobs,
+					noconfirm: build
+						.noconfirm
+						.then(|| NoConfirm::new(&build.allow_install_script)),
+					local_repo: None,
+				};
+				action_upgrade::upgrade_real(*devel, &paths, &ignored_set, &options);
 			}
 		}
 	};
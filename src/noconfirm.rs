@@ -0,0 +1,65 @@
+use colored::Colorize;
+use std::collections::HashSet;
+
+/// Local changes (or the lack of an upstream merge) need a human review
+pub const EXIT_REVIEW_REQUIRED: i32 = 11;
+/// A built package contains SUID/SGID files, or files with capabilities
+pub const EXIT_SUID_FILES: i32 = 12;
+/// A built package has an .INSTALL script that is not allowlisted
+pub const EXIT_INSTALL_SCRIPT: i32 = 13;
+/// Installing packages with pacman failed
+pub const EXIT_PACMAN_FAILED: i32 = 14;
+/// A choice between several packages is needed, e.g. between providers
+pub const EXIT_CHOICE_REQUIRED: i32 = 15;
+/// A built package violates a blocking rule of the tar_check policy
+pub const EXIT_POLICY_VIOLATION: i32 = 16;
+/// A built package cannot be checked for files that it would overwrite
+pub const EXIT_CONFLICT_CHECK_FAILED: i32 = 17;
+
+/// Policies used instead of asking the user, when running with `--noconfirm`
+#[derive(Debug, Default)]
+pub struct NoConfirm {
+	/// Packages allowed to have an .INSTALL script
+	allowed_install_scripts: HashSet<String>,
+}
+
+impl NoConfirm {
+	/// Creates the policies from `--allow-install-script`, a comma-separated package list
+	pub fn new(allowed_install_scripts: &Option<String>) -> NoConfirm {
+		let allowed_install_scripts = allowed_install_scripts
+			.iter()
+			.flat_map(|list| list.split(','))
+			.map(str::trim)
+			.filter(|name| !name.is_empty())
+			.map(str::to_string)
+			.collect();
+		NoConfirm {
+			allowed_install_scripts,
+		}
+	}
+
+	pub fn allows_install_script(&self, pkgname: &str) -> bool {
+		self.allowed_install_scripts.contains(pkgname)
+	}
+}
+
+/// Explains why a non-interactive run cannot continue, and exits with the given code
+pub fn reject(exit_code: i32, reason: &str) -> ! {
+	eprintln!("{} {}", "Rejected (--noconfirm):".bold().red(), reason);
+	std::process::exit(exit_code)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::noconfirm::*;
+
+	#[test]
+	fn test_allowed_install_scripts() {
+		let noconfirm = NoConfirm::new(&Some("foo, bar,,baz".to_string()));
+		assert!(noconfirm.allows_install_script("foo"));
+		assert!(noconfirm.allows_install_script("bar"));
+		assert!(noconfirm.allows_install_script("baz"));
+		assert!(!noconfirm.allows_install_script("qux"));
+		assert!(!NoConfirm::new(&None).allows_install_script("foo"));
+	}
+}
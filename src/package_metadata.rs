@@ -0,0 +1,333 @@
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use sha2::Digest;
+use sha2::Sha256;
+use srcinfo::Srcinfo;
+use std::fmt;
+
+/// Metadata in the `key = value` format of `.PKGINFO` and `.BUILDINFO`.
+/// Lists are stored as repeated keys, like `depend = glibc`.
+#[derive(Debug, Default)]
+pub struct PackageInfo {
+	values: IndexMap<String, Vec<String>>,
+}
+
+impl PackageInfo {
+	pub fn parse(content: &str) -> PackageInfo {
+		let mut values: IndexMap<String, Vec<String>> = IndexMap::new();
+		for line in content.lines() {
+			if line.starts_with('#') {
+				continue;
+			}
+			if let Some((key, value)) = line.split_once(" = ") {
+				let value = value.trim();
+				if !value.is_empty() {
+					values
+						.entry(key.trim().to_string())
+						.or_default()
+						.push(value.to_string());
+				}
+			}
+		}
+		PackageInfo { values }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.values.is_empty()
+	}
+
+	pub fn get(&self, key: &str) -> &[String] {
+		self.values.get(key).map_or(&[], Vec::as_slice)
+	}
+
+	pub fn first(&self, key: &str) -> Option<&str> {
+		self.get(key).first().map(String::as_str)
+	}
+}
+
+impl fmt::Display for PackageInfo {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		for (key, values) in &self.values {
+			for value in values {
+				match value.parse::<u64>() {
+					Ok(bytes) if key == "size" => {
+						writeln!(f, "{} = {} ({})", key, value, human_size(bytes))?
+					}
+					_ => writeln!(f, "{} = {}", key, value)?,
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+fn human_size(bytes: u64) -> String {
+	let kib = bytes as f64 / 1024.0;
+	if kib >= 1024.0 * 1024.0 {
+		format!("{:.1} GiB", kib / 1024.0 / 1024.0)
+	} else if kib >= 1024.0 {
+		format!("{:.1} MiB", kib / 1024.0)
+	} else {
+		format!("{:.1} KiB", kib)
+	}
+}
+
+/// Size and SHA-256 digest of a regular file in the package archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDigest {
+	pub size: u64,
+	pub sha256: String,
+}
+
+impl FileDigest {
+	pub fn new(size: u64, hasher: Sha256) -> FileDigest {
+		let sha256 = hasher
+			.finalize()
+			.iter()
+			.map(|byte| format!("{:02x}", byte))
+			.collect();
+		FileDigest { size, sha256 }
+	}
+
+	pub fn of_bytes(bytes: &[u8]) -> FileDigest {
+		FileDigest::new(bytes.len() as u64, Sha256::new_with_prefix(bytes))
+	}
+}
+
+/// An entry of the `.MTREE` file list
+#[derive(Debug, PartialEq, Eq)]
+pub struct MtreeEntry {
+	/// Path as stored in the archive, without the leading `./`
+	pub path: String,
+	/// `file`, `dir` or `link`
+	pub kind: String,
+	pub size: Option<u64>,
+	pub sha256: Option<String>,
+}
+
+/// Parses the (already decompressed) `.MTREE` file, as written by bsdtar
+pub fn parse_mtree(content: &str) -> Vec<MtreeEntry> {
+	let mut defaults: IndexMap<&str, &str> = IndexMap::new();
+	let mut result = Vec::new();
+	for line in content.lines() {
+		let mut words = line.split_whitespace();
+		let first = match words.next() {
+			Some(first) if !first.starts_with('#') => first,
+			_ => continue,
+		};
+		let keywords = words.filter_map(|word| word.split_once('='));
+		if first == "/set" {
+			defaults.extend(keywords);
+		} else if first == "/unset" {
+			for word in line.split_whitespace().skip(1) {
+				defaults.remove(word);
+			}
+		} else if let Some(path) = first.strip_prefix("./") {
+			let mut keywords_with_defaults = defaults.clone();
+			keywords_with_defaults.extend(keywords);
+			result.push(MtreeEntry {
+				path: unescape(path),
+				kind: keywords_with_defaults
+					.get("type")
+					.unwrap_or(&"file")
+					.to_string(),
+				size: keywords_with_defaults
+					.get("size")
+					.and_then(|size| size.parse().ok()),
+				sha256: keywords_with_defaults
+					.get("sha256digest")
+					.map(|sha256| sha256.to_string()),
+			});
+		}
+	}
+	result
+}
+
+/// Decodes the octal escapes used by mtree, like `\040` for a space
+fn unescape(path: &str) -> String {
+	let bytes = path.as_bytes();
+	let mut result = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let octal = bytes
+			.get(i + 1..i + 4)
+			.filter(|digits| bytes[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)))
+			.and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+		match octal {
+			Some(byte) => {
+				result.push(byte);
+				i += 4;
+			}
+			None => {
+				result.push(bytes[i]);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Compares the regular files of the archive with the sizes and digests listed in `.MTREE`
+pub fn verify_mtree(mtree: &[MtreeEntry], files: &IndexMap<String, FileDigest>) -> Vec<String> {
+	let mut result = Vec::new();
+	let mut listed = IndexSet::new();
+	for entry in mtree.iter().filter(|entry| entry.kind == "file") {
+		listed.insert(entry.path.as_str());
+		let digest = match files.get(&entry.path) {
+			Some(digest) => digest,
+			None => {
+				result.push(format!("{} is listed in .MTREE, but missing", entry.path));
+				continue;
+			}
+		};
+		match entry.size {
+			Some(size) if size != digest.size => result.push(format!(
+				"{} has size {}, but .MTREE says {}",
+				entry.path, digest.size, size
+			)),
+			_ => {}
+		}
+		match &entry.sha256 {
+			Some(sha256) if sha256 != &digest.sha256 => result.push(format!(
+				"{} has a different SHA-256 digest than listed in .MTREE",
+				entry.path
+			)),
+			None => result.push(format!("{} has no SHA-256 digest in .MTREE", entry.path)),
+			_ => {}
+		}
+	}
+	for path in files.keys() {
+		if path != ".MTREE" && !listed.contains(path.as_str()) {
+			result.push(format!("{} is not listed in .MTREE", path));
+		}
+	}
+	result
+}
+
+/// Differences between the `.PKGINFO` of a built package and the reviewed `.SRCINFO`.
+/// Packages with a `pkgver()` function are expected to differ in the version.
+pub fn srcinfo_mismatches(pkginfo: &PackageInfo, srcinfo: &Srcinfo) -> Vec<String> {
+	let pkgname = pkginfo.first("pkgname").unwrap_or_default();
+	let package = match srcinfo.pkg(pkgname) {
+		Some(package) => package,
+		None => return vec![format!("package {} is not in .SRCINFO", pkgname)],
+	};
+	let mut result = Vec::new();
+	let version = pkginfo.first("pkgver").unwrap_or_default();
+	if version != srcinfo.version() {
+		result.push(format!(
+			"version is {} in the package, but {} in .SRCINFO",
+			version,
+			srcinfo.version()
+		));
+	}
+	let arch = pkginfo.first("arch").unwrap_or("any");
+	// The srcinfo parser already merges the global fields into each package
+	let fields = [
+		("depend", "depends", &package.depends),
+		("optdepend", "optdepends", &package.optdepends),
+		("provides", "provides", &package.provides),
+		("conflict", "conflicts", &package.conflicts),
+		("replaces", "replaces", &package.replaces),
+	];
+	for (pkginfo_key, srcinfo_key, declared) in fields {
+		let declared: IndexSet<&str> = declared.arch(arch).collect();
+		let built: IndexSet<&str> = pkginfo
+			.get(pkginfo_key)
+			.iter()
+			.map(String::as_str)
+			.collect();
+		for value in built.difference(&declared) {
+			result.push(format!(
+				"{} {} is in the package, but not in .SRCINFO",
+				srcinfo_key, value
+			));
+		}
+		for value in declared.difference(&built) {
+			result.push(format!(
+				"{} {} is in .SRCINFO, but not in the package",
+				srcinfo_key, value
+			));
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::package_metadata::*;
+	use std::str::FromStr;
+
+	const PKGINFO: &str = "# Generated by makepkg 6.1.0
+pkgname = foo
+pkgbase = foo
+pkgver = 1.0-1
+size = 3145728
+arch = x86_64
+depend = glibc
+depend = curl
+provides = libfoo.so=1-64
+backup = etc/foo.conf
+";
+
+	#[test]
+	fn test_package_info() {
+		let info = PackageInfo::parse(PKGINFO);
+		assert_eq!(info.first("pkgname"), Some("foo"));
+		assert_eq!(info.get("depend"), ["glibc", "curl"]);
+		assert!(info.get("conflict").is_empty());
+		assert!(info.to_string().contains("size = 3145728 (3.0 MiB)"));
+	}
+
+	#[test]
+	fn test_verify_mtree() {
+		let mtree = parse_mtree(
+			"#mtree
+/set type=file uid=0 gid=0 mode=644
+./.PKGINFO time=1700000000.0 size=5 sha256digest=2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824
+./usr time=1700000000.0 mode=755 type=dir
+./usr/bin/foo\\040bar time=1700000000.0 mode=755 size=3 sha256digest=0000
+./usr/share/missing time=1700000000.0 size=0 sha256digest=e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855
+",
+		);
+		assert_eq!(mtree[2].path, "usr/bin/foo bar");
+		assert_eq!(mtree[1].kind, "dir");
+		let mut files = IndexMap::new();
+		files.insert(".PKGINFO".to_string(), FileDigest::of_bytes(b"hello"));
+		files.insert("usr/bin/foo bar".to_string(), FileDigest::of_bytes(b"abcd"));
+		files.insert("usr/bin/extra".to_string(), FileDigest::of_bytes(b""));
+		assert_eq!(
+			verify_mtree(&mtree, &files),
+			[
+				"usr/bin/foo bar has size 4, but .MTREE says 3",
+				"usr/bin/foo bar has a different SHA-256 digest than listed in .MTREE",
+				"usr/share/missing is listed in .MTREE, but missing",
+				"usr/bin/extra is not listed in .MTREE",
+			]
+		);
+	}
+
+	#[test]
+	fn test_srcinfo_mismatches() {
+		let srcinfo = Srcinfo::from_str(
+			"pkgbase = foo
+	pkgver = 1.0
+	pkgrel = 1
+	arch = x86_64
+	depends = glibc
+	depends_x86_64 = lib32-foo
+
+pkgname = foo
+	provides = libfoo.so=1-64
+",
+		)
+		.unwrap();
+		assert_eq!(
+			srcinfo_mismatches(&PackageInfo::parse(PKGINFO), &srcinfo),
+			[
+				"depends curl is in the package, but not in .SRCINFO",
+				"depends lib32-foo is in .SRCINFO, but not in the package",
+			]
+		);
+	}
+}
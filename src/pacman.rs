@@ -1,4 +1,6 @@
 use crate::alpm_wrapper::new_alpm_wrapper;
+use crate::noconfirm;
+use crate::noconfirm::NoConfirm;
 use crate::rua_environment;
 use crate::terminal_util;
 use indexmap::IndexSet;
@@ -20,7 +22,11 @@ pub fn get_ignored_packages() -> Result<HashSet<String>, String> {
 	Ok(output.lines().map(ToOwned::to_owned).collect())
 }
 
-fn ensure_packages_installed(mut packages: Vec<(String, PathBuf)>, base_args: &[&str]) {
+fn ensure_packages_installed(
+	mut packages: Vec<(String, PathBuf)>,
+	base_args: &[&str],
+	noconfirm: Option<&NoConfirm>,
+) {
 	let mut attempt = 0;
 	while !packages.is_empty() {
 		{
@@ -42,6 +48,22 @@ fn ensure_packages_installed(mut packages: Vec<(String, PathBuf)>, base_args: &[
 					.collect_vec()
 					.join(" ")
 			);
+			if noconfirm.is_some() {
+				let exit_status = Command::new(rua_environment::sudo_command())
+					.arg("pacman")
+					.args(base_args)
+					.arg("--noconfirm")
+					.arg("--")
+					.args(&list)
+					.status();
+				if !exit_status.map(|c| c.success()).unwrap_or(false) {
+					noconfirm::reject(
+						noconfirm::EXIT_PACMAN_FAILED,
+						"failed to install packages with pacman",
+					);
+				}
+				break;
+			}
 			if attempt == 0 {
 				eprint!(
 					"Enter S to `{}` install it, or install manually and press M when done. ",
@@ -82,21 +104,39 @@ fn ensure_packages_installed(mut packages: Vec<(String, PathBuf)>, base_args: &[
 	}
 }
 
-pub fn ensure_aur_packages_installed(packages: Vec<(String, PathBuf)>, is_dependency: bool) {
+pub fn ensure_aur_packages_installed(
+	packages: Vec<(String, PathBuf)>,
+	is_dependency: bool,
+	noconfirm: Option<&NoConfirm>,
+) {
 	if is_dependency {
-		ensure_packages_installed(packages, &["-U", "--asdeps"]);
+		ensure_packages_installed(packages, &["-U", "--asdeps"], noconfirm);
 	} else {
-		ensure_packages_installed(packages, &["-U"]);
+		ensure_packages_installed(packages, &["-U"], noconfirm);
 	}
 }
 
-pub fn ensure_pacman_packages_installed(packages: IndexSet<String>) {
+/// Installs repository dependencies, like `foo` or `foo>=2`.
+/// Packages that are already installed are upgraded without `--asdeps`,
+/// so that their install reason is kept.
+pub fn ensure_pacman_packages_installed(packages: IndexSet<String>, noconfirm: Option<&NoConfirm>) {
+	let alpm = new_alpm_wrapper();
+	let mut upgrades: Vec<(String, PathBuf)> = Vec::new();
 	let mut map: Vec<(String, PathBuf)> = Vec::new();
 	for package in packages {
 		let path = Path::new(&package).to_path_buf();
-		map.push((package, path));
+		let name = package.split(['<', '>', '=']).next().unwrap_or(&package);
+		let installed = alpm
+			.installed_version(name)
+			.expect("Failed to check install status for a package");
+		if installed.is_some() {
+			upgrades.push((package, path));
+		} else {
+			map.push((package, path));
+		}
 	}
-	ensure_packages_installed(map, &["-S", "--asdeps", "--needed"]);
+	ensure_packages_installed(upgrades, &["-S", "--needed"], noconfirm);
+	ensure_packages_installed(map, &["-S", "--asdeps", "--needed"], noconfirm);
 }
 
 // Architecture as defined in the local pacman configuration
@@ -1,4 +1,5 @@
 use crate::aur_rpc_utils::info_map;
+use crate::aur_wrapper::new_aur_wrapper;
 use crate::print_format::date;
 use crate::print_format::opt;
 use crate::print_format::print_indent;
@@ -8,7 +9,7 @@ use colored::*;
 use termize::dimensions_stdout;
 
 pub fn info(pkgs: &[String], verbose: bool) -> Result<(), Error> {
-	let pkg_map = info_map(pkgs)?;
+	let pkg_map = info_map(pkgs, &*new_aur_wrapper())?;
 
 	let mut all_pkgs = Vec::with_capacity(pkg_map.len());
 
@@ -0,0 +1,113 @@
+use crate::git_utils;
+use crate::rua_paths::RuaPaths;
+use chrono::SecondsFormat;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// One approval of a pkgbase, a line in its JSONL ledger file
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+	/// Approved commit of the review repository.
+	/// With uncommitted changes, this is a snapshot commit on top of HEAD.
+	pub commit: String,
+	/// Tree of the approved commit, identifying the exact files
+	pub tree: String,
+	/// `upstream/master` at the time of the review
+	pub upstream_commit: Option<String>,
+	/// RFC 3339, UTC
+	pub timestamp: String,
+	pub user: String,
+	/// Whether the approved files differ from upstream AUR
+	pub local_changes: bool,
+	/// Approved automatically with `--noconfirm`, instead of by the user
+	pub noconfirm: bool,
+}
+
+/// Appends the current state of the review repository to the ledger of the pkgbase.
+/// Uncommitted changes are approved too, so they are recorded in a snapshot commit.
+pub fn record_approval(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths, noconfirm: bool) {
+	let rev_parse = |rev: &str| git_utils::rev_parse(dir, rev, rua_paths);
+	let commit = git_utils::snapshot_working_tree(dir, rua_paths);
+	git_utils::update_ref(dir, git_utils::APPROVED_REF, &commit, rua_paths);
+	git_utils::delete_ref(dir, git_utils::REVIEWED_UPSTREAM_REF, rua_paths);
+	let tree = rev_parse(&format!("{}^{{tree}}", commit)).expect("Failed to get the reviewed tree");
+	let entry = LedgerEntry {
+		// untracked files are approved and built too, so not `git_utils::identical_to_upstream`
+		local_changes: rev_parse("upstream/master^{tree}").as_ref() != Some(&tree),
+		tree,
+		commit,
+		upstream_commit: rev_parse("upstream/master"),
+		timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+		user: std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+		noconfirm,
+	};
+	append(&rua_paths.ledger_path(pkgbase), &entry);
+}
+
+fn append(path: &Path, entry: &LedgerEntry) {
+	let line = serde_json::to_string(entry).expect("Failed to serialize review ledger entry");
+	let mut file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.unwrap_or_else(|err| panic!("Failed to open review ledger {:?}, {}", path, err));
+	writeln!(file, "{}", line)
+		.unwrap_or_else(|err| panic!("Failed to write review ledger {:?}, {}", path, err));
+}
+
+/// Reads all entries of a ledger file, oldest first
+pub fn read(path: &Path) -> Vec<LedgerEntry> {
+	let content = fs::read_to_string(path)
+		.unwrap_or_else(|err| panic!("Failed to read review ledger {:?}, {}", path, err));
+	parse(&content).unwrap_or_else(|err| panic!("Broken review ledger {:?}, {}", path, err))
+}
+
+/// The latest entry of a ledger file, if the pkgbase was ever approved
+pub fn last_approval(path: &Path) -> Option<LedgerEntry> {
+	if path.exists() {
+		read(path).pop()
+	} else {
+		None
+	}
+}
+
+fn parse(content: &str) -> Result<Vec<LedgerEntry>, String> {
+	content
+		.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty())
+		.map(|(index, line)| {
+			serde_json::from_str(line).map_err(|err| format!("line {}: {}", index + 1, err))
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::review_ledger::*;
+
+	#[test]
+	fn test_ledger_round_trip() {
+		let entry = LedgerEntry {
+			commit: "a".repeat(40),
+			tree: "b".repeat(40),
+			upstream_commit: None,
+			timestamp: "2026-10-18T07:05:09Z".to_string(),
+			user: "alice".to_string(),
+			local_changes: true,
+			noconfirm: false,
+		};
+		let line = serde_json::to_string(&entry).unwrap();
+		let content = format!("{}\n\n{}\n", line, line);
+		let parsed = parse(&content).unwrap();
+		assert_eq!(parsed.len(), 2);
+		assert_eq!(parsed[1], entry);
+		let error = parse(&format!("{}\n{{}}\n", line)).unwrap_err();
+		assert!(error.starts_with("line 2:"), "{}", error);
+	}
+}
@@ -1,12 +1,43 @@
 use crate::git_utils;
+use crate::noconfirm;
+use crate::noconfirm::NoConfirm;
+use crate::review_ledger;
 use crate::rua_paths::RuaPaths;
+use crate::srcinfo_diff::SrcinfoDiff;
+use crate::suspicious_patterns;
+use crate::suspicious_patterns::Finding;
 use crate::terminal_util;
 use crate::wrapped;
 use colored::Colorize;
+use itertools::Itertools;
 use log::debug;
+use srcinfo::Srcinfo;
+use std::fs;
 use std::path::Path;
+use std::str::FromStr;
 
-pub fn review_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
+pub fn review_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths, noconfirm: Option<&NoConfirm>) {
+	fetch_repo(dir, pkgbase, rua_paths);
+	let build_dir = rua_paths.build_dir(pkgbase);
+	if build_dir.exists() && git_utils::is_upstream_merged(dir, rua_paths) {
+		eprintln!("WARNING: your AUR repo is up-to-date.");
+		eprintln!(
+			"If you continue, the build directory will be removed and the build will be re-run."
+		);
+		eprintln!("If you don't want that, consider resolving the situation manually,");
+		let build_dir = terminal_util::escape_bash_arg(
+			build_dir
+				.to_str()
+				.unwrap_or_else(|| panic!("Failed to stringify build directory {:?}", build_dir)),
+		);
+		eprintln!("for example:    rua builddir {}", build_dir);
+		eprintln!();
+	}
+	review_fetched_repo(dir, pkgbase, rua_paths, noconfirm);
+}
+
+/// Clones the AUR repository into an empty `dir`, or fetches upstream changes otherwise
+pub fn fetch_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
 	let mut dir_contents = dir.read_dir().unwrap_or_else(|err| {
 		panic!(
 			"{}:{} Failed to read directory for reviewing, {}",
@@ -20,37 +51,66 @@ pub fn review_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
 		git_utils::init_repo(pkgbase, dir, rua_paths);
 	} else {
 		debug!("Directory {:?} is not empty, fetching new version", &dir);
-		git_utils::fetch(dir, rua_paths);
+		git_utils::fetch(pkgbase, dir, rua_paths);
 	}
+}
 
-	let build_dir = rua_paths.build_dir(pkgbase);
-	if build_dir.exists() && git_utils::is_upstream_merged(dir, rua_paths) {
-		eprintln!("WARNING: your AUR repo is up-to-date.");
-		eprintln!(
-			"If you continue, the build directory will be removed and the build will be re-run."
-		);
-		eprintln!("If you don't want that, consider resolving the situation manually,");
-		let build_dir = terminal_util::escape_bash_arg(
-			build_dir
-				.to_str()
-				.unwrap_or_else(|| panic!("Failed to stringify build directory {:?}", build_dir)),
-		);
-		eprintln!("for example:    rua builddir {}", build_dir);
-		eprintln!();
+/// Reviews a repository that is already fetched, until the user accepts it
+pub fn review_fetched_repo(
+	dir: &Path,
+	pkgbase: &str,
+	rua_paths: &RuaPaths,
+	noconfirm: Option<&NoConfirm>,
+) {
+	if noconfirm.is_some() {
+		accept_if_identical_to_upstream(dir, pkgbase, rua_paths);
+		return;
 	}
 
+	let mut shown_state = None;
+	let mut rebased_upstream_shown = false;
+	let mut shown_findings = Vec::new();
+	let mut shown_srcinfo_summary = None;
 	loop {
 		eprintln!("\nReviewing {:?}. ", dir);
+		if git_utils::is_merging(dir, rua_paths) {
+			resolve_merge(dir, rua_paths);
+			continue;
+		}
 		let is_upstream_merged = git_utils::is_upstream_merged(dir, rua_paths);
+		let (base, target) = review_range(dir, is_upstream_merged, rua_paths);
+		let state = ReviewState::current(dir, rua_paths);
+		// Both run git diff, and the summary also makepkg --printsrcinfo. Not on every prompt.
+		if shown_state.as_ref() != Some(&state) {
+			let findings = suspicious_findings(dir, base, target, rua_paths);
+			let srcinfo_summary = srcinfo_summary(dir, base, target, rua_paths);
+			if (&findings, &srcinfo_summary) != (&shown_findings, &shown_srcinfo_summary) {
+				if let Some(summary) = &srcinfo_summary {
+					eprintln!("\n{}", summary);
+				}
+				print_findings(&findings);
+				shown_findings = findings;
+				shown_srcinfo_summary = srcinfo_summary;
+			}
+			shown_state = Some(state);
+		}
 		let identical_to_upstream =
 			is_upstream_merged && git_utils::identical_to_upstream(dir, rua_paths);
+		let unreviewed_rebase =
+			is_upstream_merged && git_utils::has_unreviewed_rebase(dir, rua_paths);
 		if is_upstream_merged {
 			eprint!(
 				"{}{}, ",
 				"[S]".bold().green(),
 				"=run shellcheck on PKGBUILD".green()
 			);
-			if identical_to_upstream {
+			if unreviewed_rebase {
+				eprint!(
+					"{}{}, ",
+					"[D]".bold().green(),
+					"=view upstream changes that your patches were rebased onto".green()
+				);
+			} else if identical_to_upstream {
 				eprint!("{}, ", "[D]=(identical to upstream, empty diff)".dimmed());
 			} else {
 				eprint!("{}{}, ", "[D]".bold().green(), "=view your changes".green());
@@ -71,12 +131,42 @@ pub fn review_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
 				"[S]=(shellcheck not available until you merge)".dimmed()
 			);
 		}
+		if git_utils::has_built_commit(dir, rua_paths) {
+			if git_utils::identical_to_built(dir, rua_paths) {
+				eprint!("{}, ", "[B]=(identical to the last build)".dimmed());
+			} else {
+				eprint!(
+					"{}{}, ",
+					"[B]".bold().green(),
+					"=view changes since the last build".green()
+				);
+			}
+		}
 		eprint!(
 			"{}{}, ",
 			"[T]".bold().cyan(),
 			"=run shell to edit/inspect".cyan()
 		);
-		if is_upstream_merged {
+		let conflict_markers = if is_upstream_merged {
+			git_utils::files_with_conflict_markers(dir, rua_paths)
+		} else {
+			Vec::new()
+		};
+		if !conflict_markers.is_empty() {
+			eprint!(
+				"{} ",
+				format!(
+					"[O]=(conflict markers remain in {})",
+					conflict_markers.join(", ")
+				)
+				.dimmed()
+			);
+		} else if unreviewed_rebase && !rebased_upstream_shown {
+			eprint!(
+				"{}",
+				"[O]=(view the rebased upstream changes with [D] first) ".dimmed()
+			);
+		} else if is_upstream_merged {
 			eprint!("{}{}. ", "[O]".bold().red(), "=ok, use package".red());
 		} else {
 			eprint!(
@@ -94,14 +184,318 @@ pub fn review_repo(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
 			if let Err(err) = wrapped::shellcheck(&Some(dir.join("PKGBUILD"))) {
 				eprintln!("{}", err);
 			};
+		} else if &user_input == "d" && unreviewed_rebase {
+			git_utils::show_rebased_upstream_diff(dir, rua_paths);
+			rebased_upstream_shown = true;
 		} else if &user_input == "d" && is_upstream_merged {
 			git_utils::show_upstream_diff(dir, false, rua_paths);
 		} else if &user_input == "d" && !is_upstream_merged {
 			git_utils::show_upstream_diff(dir, true, rua_paths);
+		} else if &user_input == "b" && git_utils::has_built_commit(dir, rua_paths) {
+			git_utils::show_built_diff(dir, rua_paths);
 		} else if &user_input == "m" && !is_upstream_merged {
-			git_utils::merge_upstream(dir, rua_paths);
-		} else if &user_input == "o" && is_upstream_merged {
+			if !git_utils::merge_upstream(dir, rua_paths) {
+				eprintln!("{}", "Merging upstream changes failed.".bold().red());
+			}
+		} else if &user_input == "o"
+			&& is_upstream_merged
+			&& conflict_markers.is_empty()
+			&& (!unreviewed_rebase || rebased_upstream_shown)
+		{
+			review_ledger::record_approval(dir, pkgbase, rua_paths, false);
 			break;
 		}
 	}
 }
+
+/// What the review loop shows is computed from this, and only needs an update when it changes
+#[derive(PartialEq, Eq)]
+struct ReviewState {
+	/// HEAD, or a snapshot with uncommitted changes, see `git_utils::snapshot_working_tree`
+	working_tree: Option<String>,
+	upstream: Option<String>,
+	reviewed_upstream: Option<String>,
+	built: Option<String>,
+}
+
+impl ReviewState {
+	fn current(dir: &Path, rua_paths: &RuaPaths) -> ReviewState {
+		let rev_parse = |rev: &str| git_utils::rev_parse(dir, rev, rua_paths);
+		ReviewState {
+			working_tree: rev_parse("HEAD")
+				.map(|_| git_utils::snapshot_working_tree(dir, rua_paths)),
+			upstream: rev_parse("upstream/master"),
+			reviewed_upstream: rev_parse(git_utils::REVIEWED_UPSTREAM_REF),
+			built: rev_parse(git_utils::BUILT_REF),
+		}
+	}
+}
+
+/// Asks how to proceed with a merge that stopped on conflicts
+fn resolve_merge(dir: &Path, rua_paths: &RuaPaths) {
+	let conflicted = git_utils::conflicted_files(dir, rua_paths);
+	if conflicted.is_empty() {
+		eprintln!("Merge of upstream changes is in progress, all conflicts are resolved.");
+		eprint!("{}{}, ", "[C]".bold().green(), "=commit the merge".green());
+	} else {
+		eprintln!(
+			"{} Merging upstream changes stopped on conflicts in:",
+			"Conflict:".bold().red()
+		);
+		for file in &conflicted {
+			eprintln!("  {}", file);
+		}
+		eprint!(
+			"{}{}, ",
+			"[U]".bold().yellow(),
+			"=take upstream version of conflicting files, dropping your changes in them".yellow()
+		);
+	}
+	eprint!("{}{}, ", "[D]".bold().green(), "=view conflicts".green());
+	eprint!(
+		"{}{}, ",
+		"[T]".bold().cyan(),
+		"=run shell to resolve, then `git add` the files".cyan()
+	);
+	eprint!("{}{}. ", "[A]".bold().red(), "=abort the merge".red());
+	let user_input = terminal_util::read_line_lowercase();
+	if &user_input == "t" {
+		eprintln!("Edit the conflicting files and `git add` them, or run `git commit` when done.");
+		eprintln!("Exit the shell with `logout` or Ctrl-D...");
+		terminal_util::run_env_command(dir, "SHELL", "bash", &[]);
+	} else if &user_input == "d" {
+		git_utils::show_diff(dir, rua_paths);
+	} else if &user_input == "a" {
+		git_utils::abort_merge(dir, rua_paths);
+	} else if &user_input == "u" && !conflicted.is_empty() {
+		git_utils::take_upstream(dir, &conflicted, rua_paths);
+	} else if &user_input == "c"
+		&& conflicted.is_empty()
+		&& !git_utils::conclude_merge(dir, rua_paths)
+	{
+		eprintln!("{}", "Committing the merge failed.".bold().red());
+	}
+}
+
+/// The changes under review, as (base, target) revisions. The target is `None` for the working tree.
+/// Upstream changes are reviewed if they are not merged yet, or were merged by a rebase
+/// of the local patches. Otherwise everything since the last build (or since the beginning).
+fn review_range(
+	dir: &Path,
+	is_upstream_merged: bool,
+	rua_paths: &RuaPaths,
+) -> (&'static str, Option<&'static str>) {
+	if !is_upstream_merged {
+		let has_head = git_utils::rev_parse(dir, "HEAD", rua_paths).is_some();
+		let base = if has_head {
+			"HEAD"
+		} else {
+			git_utils::EMPTY_TREE
+		};
+		(base, Some("upstream/master"))
+	} else if git_utils::has_unreviewed_rebase(dir, rua_paths) {
+		(git_utils::REVIEWED_UPSTREAM_REF, Some("upstream/master"))
+	} else if git_utils::has_built_commit(dir, rua_paths) {
+		(git_utils::BUILT_REF, None)
+	} else {
+		(git_utils::EMPTY_TREE, None)
+	}
+}
+
+/// Scans the changes under review. Install scripts are scanned as a whole.
+fn suspicious_findings(
+	dir: &Path,
+	base: &str,
+	target: Option<&str>,
+	rua_paths: &RuaPaths,
+) -> Vec<Finding> {
+	let changes = git_utils::diff_text(dir, base, target, &[], rua_paths);
+	let install_scripts = install_scripts(dir, target, rua_paths);
+	let install_scripts = install_scripts
+		.iter()
+		.map(String::as_str)
+		.collect::<Vec<_>>();
+	let install_scripts = if install_scripts.is_empty() {
+		String::new()
+	} else {
+		git_utils::diff_text(
+			dir,
+			git_utils::EMPTY_TREE,
+			target,
+			&install_scripts,
+			rua_paths,
+		)
+	};
+	suspicious_patterns::scan_diff(&format!("{}{}", changes, install_scripts))
+}
+
+/// Pathspecs of the install scripts named by `install=` in .SRCINFO.
+/// If .SRCINFO cannot be parsed, all `*.install` files are taken instead.
+fn install_scripts(dir: &Path, target: Option<&str>, rua_paths: &RuaPaths) -> Vec<String> {
+	let srcinfo = srcinfo_content(dir, target, rua_paths)
+		.and_then(|content| Srcinfo::from_str(&content).ok());
+	match srcinfo {
+		Some(srcinfo) => srcinfo
+			.pkgs
+			.iter()
+			.filter_map(|pkg| pkg.install.as_deref())
+			.map(|install| format!(":(literal){}", install))
+			.unique()
+			.collect(),
+		None => vec!["*.install".to_string()],
+	}
+}
+
+/// Content of .SRCINFO at the revision, or in the working tree
+fn srcinfo_content(dir: &Path, rev: Option<&str>, rua_paths: &RuaPaths) -> Option<String> {
+	match rev {
+		Some(rev) => git_utils::show_file(dir, rev, ".SRCINFO", rua_paths),
+		None => fs::read_to_string(dir.join(".SRCINFO")).ok(),
+	}
+}
+
+/// Summary of .SRCINFO changes under review, if there is anything to show
+fn srcinfo_summary(
+	dir: &Path,
+	base: &str,
+	target: Option<&str>,
+	rua_paths: &RuaPaths,
+) -> Option<String> {
+	let parse = |content: String| Srcinfo::from_str(&content).ok();
+	let old = git_utils::show_file(dir, base, ".SRCINFO", rua_paths).and_then(parse);
+	let new = srcinfo_content(dir, target, rua_paths);
+	let mut summary = String::new();
+	match new.and_then(parse) {
+		Some(new) => {
+			let diff = SrcinfoDiff::new(old.as_ref(), &new);
+			if !diff.is_empty() {
+				summary.push_str(&format!("{}:\n{}", "Changes in .SRCINFO".bold(), diff));
+			}
+		}
+		None => summary.push_str(&format!(
+			"{} .SRCINFO is missing or cannot be parsed\n",
+			"WARNING:".bold().red()
+		)),
+	}
+	let changed = git_utils::changed_files(dir, base, target, rua_paths);
+	let pkgbuild_changed = changed.iter().any(|file| file == "PKGBUILD");
+	let srcinfo_changed = changed.iter().any(|file| file == ".SRCINFO");
+	if pkgbuild_changed != srcinfo_changed {
+		let (changed, unchanged) = if pkgbuild_changed {
+			("PKGBUILD", ".SRCINFO")
+		} else {
+			(".SRCINFO", "PKGBUILD")
+		};
+		summary.push_str(&format!(
+			"{} {} changed, but {} did not. The summary above might not match what is built\n",
+			"WARNING:".bold().red(),
+			changed,
+			unchanged
+		));
+	}
+	if target.is_none() {
+		match generated_srcinfo_mismatches(dir, rua_paths) {
+			Ok(mismatches) if mismatches.is_empty() => {}
+			Ok(mismatches) => summary.push_str(&format!(
+				"{} .SRCINFO does not match PKGBUILD, do not trust the summary above:\n  {}\n",
+				"WARNING:".bold().red(),
+				mismatches.join("\n  ")
+			)),
+			Err(err) => summary.push_str(&format!(
+				"{} cannot compare .SRCINFO with PKGBUILD, {}\n",
+				"WARNING:".bold().red(),
+				err
+			)),
+		}
+	}
+	Some(summary).filter(|summary| !summary.is_empty())
+}
+
+fn generated_srcinfo_mismatches(dir: &Path, rua_paths: &RuaPaths) -> Result<Vec<String>, String> {
+	let dir = dir
+		.to_str()
+		.ok_or_else(|| format!("non-UTF8 directory {:?}", dir))?;
+	wrapped::srcinfo_mismatches(dir, rua_paths)
+}
+
+fn print_findings(findings: &[Finding]) {
+	if findings.is_empty() {
+		return;
+	}
+	eprintln!(
+		"\n{} in the changes under review, check them carefully:",
+		"Suspicious patterns".bold().yellow()
+	);
+	for finding in findings {
+		eprintln!("  {}", finding);
+	}
+}
+
+/// Merges upstream changes without asking, as long as the result is exactly upstream.
+/// Local modifications need a human review, so they fail the non-interactive run.
+fn accept_if_identical_to_upstream(dir: &Path, pkgbase: &str, rua_paths: &RuaPaths) {
+	if git_utils::is_merging(dir, rua_paths) {
+		noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				"a merge is in progress in {:?}, finish it interactively",
+				dir
+			),
+		);
+	}
+	let merged =
+		git_utils::is_upstream_merged(dir, rua_paths) || git_utils::merge_upstream(dir, rua_paths);
+	if !merged && git_utils::is_merging(dir, rua_paths) {
+		// leave the repository as it was, for an interactive review later
+		git_utils::abort_merge(dir, rua_paths);
+	}
+	if !git_utils::is_upstream_merged(dir, rua_paths) {
+		noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				"failed to merge upstream changes of {} in {:?}",
+				pkgbase, dir
+			),
+		);
+	}
+	if !git_utils::identical_to_upstream(dir, rua_paths) {
+		noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				"{:?} differs from upstream AUR, review the local changes interactively",
+				dir
+			),
+		);
+	}
+	let uncommitted = git_utils::uncommitted_files(dir, rua_paths);
+	if !uncommitted.is_empty() {
+		noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				"{:?} has uncommitted files that would be built: {}, review them interactively",
+				dir,
+				uncommitted.join(", ")
+			),
+		);
+	}
+	match generated_srcinfo_mismatches(dir, rua_paths) {
+		Ok(mismatches) if mismatches.is_empty() => {}
+		Ok(mismatches) => noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				".SRCINFO of {} does not match PKGBUILD: {}",
+				pkgbase,
+				mismatches.join(", ")
+			),
+		),
+		Err(err) => noconfirm::reject(
+			noconfirm::EXIT_REVIEW_REQUIRED,
+			&format!(
+				"cannot compare .SRCINFO of {} with PKGBUILD, {}",
+				pkgbase, err
+			),
+		),
+	}
+	eprintln!("{} is identical to upstream AUR, accepting it", pkgbase);
+	review_ledger::record_approval(dir, pkgbase, rua_paths, true);
+}
@@ -44,6 +44,9 @@ pub fn prepare_environment(config: &CliArgs) {
 			env::remove_var("CLICOLOR");
 		}
 	}
+	if let Some(aur_url) = &config.aur_url {
+		env::set_var(cli_args::AUR_URL_ENVIRONMENT_VARIABLE_NAME, aur_url);
+	}
 	debug!(
 		"{} version {}",
 		env!("CARGO_PKG_NAME"),
@@ -54,3 +57,12 @@ pub fn prepare_environment(config: &CliArgs) {
 pub fn sudo_command() -> String {
 	std::env::var(cli_args::SUDO_ENVIRONMENT_VARIABLE_NAME).unwrap_or_else(|_| "sudo".to_string())
 }
+
+pub const DEFAULT_AUR_URL: &str = "https://aur.archlinux.org";
+
+/// AUR base URL without the trailing slash, such as https://aur.archlinux.org
+pub fn aur_url() -> String {
+	let url = std::env::var(cli_args::AUR_URL_ENVIRONMENT_VARIABLE_NAME)
+		.unwrap_or_else(|_| DEFAULT_AUR_URL.to_string());
+	url.trim_end_matches('/').to_string()
+}
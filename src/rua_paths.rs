@@ -26,6 +26,10 @@ pub struct RuaPaths {
 	global_review_dir: PathBuf,
 	/// Directory where built and user-reviewed package artifacts are stored
 	global_checked_tars_dir: PathBuf,
+	/// Directory with makepkg output of past builds, per pkgbase
+	global_logs_dir: PathBuf,
+	/// Directory with the append-only review ledger of each pkgbase
+	global_ledger_dir: PathBuf,
 	/// Script used to wrap `makepkg` and related commands
 	pub wrapper_bwrap_script: PathBuf,
 	/// makepkg configuration for PKGEXT
@@ -45,8 +49,7 @@ impl RuaPaths {
 			exit(1)
 		}
 
-		let dirs = &ProjectDirs::from("com.gitlab", "vn971", "rua")
-			.expect("Failed to determine XDG directories");
+		let dirs = &project_dirs();
 		std::fs::create_dir_all(dirs.config_dir())
 			.expect("Failed to create project config directory");
 		let locked_file = File::open(dirs.config_dir()).unwrap_or_else(|err| {
@@ -79,6 +82,12 @@ impl RuaPaths {
 			&dirs.config_dir().join(".system/wrap_args.sh.example"),
 			WRAP_ARGS_EXAMPLE,
 		);
+		overwrite_file(
+			&dirs
+				.config_dir()
+				.join(".system/tar_check_policy.toml.example"),
+			TAR_CHECK_POLICY_EXAMPLE,
+		);
 		let makepkg_config_loader_path = dirs.config_dir().join(MAKEPKG_CONFIG_LOADER_PATH);
 
 		wrapped::check_bubblewrap_runnable();
@@ -86,6 +95,8 @@ impl RuaPaths {
 		let global_build_dir = dirs.cache_dir().join("build");
 		let global_checked_tars_dir = dirs.data_local_dir().join("checked_tars");
 		let global_review_dir = dirs.config_dir().join("pkg");
+		let global_logs_dir = global_logs_dir();
+		let global_ledger_dir = global_ledger_dir();
 
 		std::fs::create_dir_all(&global_build_dir)
 			.expect("Failed to create global build directory");
@@ -100,12 +111,18 @@ impl RuaPaths {
 			.expect("Failed to create global checked_tars directory");
 		std::fs::create_dir_all(&global_review_dir)
 			.expect("Failed to create global review directory");
+		std::fs::create_dir_all(&global_logs_dir)
+			.expect("Failed to create global build logs directory");
+		std::fs::create_dir_all(&global_ledger_dir)
+			.expect("Failed to create global review ledger directory");
 
 		// All directories must exist upon `RuaPaths` creation.
 		RuaPaths {
 			global_build_dir,
 			global_review_dir,
 			global_checked_tars_dir,
+			global_logs_dir,
+			global_ledger_dir,
 			wrapper_bwrap_script: dirs.config_dir().join(WRAP_SCRIPT_PATH),
 			makepkg_pkgext: perform_makepkg_checks_and_return_pkgext(&makepkg_config_loader_path),
 			_global_lock: locked_file,
@@ -117,6 +134,23 @@ impl RuaPaths {
 		self.global_review_dir.join(pkgbase)
 	}
 
+	/// Pkgbases that have a review directory, sorted by name
+	pub fn reviewed_pkgbases(&self) -> Vec<String> {
+		let dir = self.global_review_dir.read_dir().unwrap_or_else(|err| {
+			panic!(
+				"Failed to read review directory {:?}, {}",
+				self.global_review_dir, err
+			)
+		});
+		let mut result: Vec<String> = dir
+			.filter_map(|entry| entry.ok())
+			.filter(|entry| entry.path().is_dir())
+			.filter_map(|entry| entry.file_name().into_string().ok())
+			.collect();
+		result.sort();
+		result
+	}
+
 	/// Same as `global_build_dir`, but for a specific pkgbase
 	pub fn build_dir(&self, pkgbase: &str) -> PathBuf {
 		self.global_build_dir.join(pkgbase)
@@ -126,6 +160,39 @@ impl RuaPaths {
 	pub fn checked_tars_dir(&self, pkg_name: &str) -> PathBuf {
 		self.global_checked_tars_dir.join(pkg_name)
 	}
+
+	/// Same as `global_logs_dir`, but for a specific pkgbase
+	pub fn logs_dir(&self, pkgbase: &str) -> PathBuf {
+		self.global_logs_dir.join(pkgbase)
+	}
+
+	/// Review ledger file of a specific pkgbase, see `global_ledger_dir`
+	pub fn ledger_path(&self, pkgbase: &str) -> PathBuf {
+		ledger_path(&self.global_ledger_dir, pkgbase)
+	}
+}
+
+fn project_dirs() -> ProjectDirs {
+	ProjectDirs::from("com.gitlab", "vn971", "rua").expect("Failed to determine XDG directories")
+}
+
+/// Build logs directory, for reading the logs without taking the RUA lock
+pub fn global_logs_dir() -> PathBuf {
+	project_dirs().data_local_dir().join("logs")
+}
+
+/// Review ledger directory, for reading the ledger without taking the RUA lock
+pub fn global_ledger_dir() -> PathBuf {
+	project_dirs().data_local_dir().join("ledger")
+}
+
+/// Policy for built packages, see `crate::tar_check_policy`
+pub fn tar_check_policy_path() -> PathBuf {
+	project_dirs().config_dir().join("tar_check_policy.toml")
+}
+
+pub fn ledger_path(global_ledger_dir: &Path, pkgbase: &str) -> PathBuf {
+	global_ledger_dir.join(format!("{}.jsonl", pkgbase))
 }
 
 fn perform_makepkg_checks_and_return_pkgext(makepkg_config_loader_path: &Path) -> String {
@@ -242,6 +309,7 @@ pub const SHELLCHECK_WRAPPER: &str = include_str!("../res/shellcheck-wrapper");
 pub const SECCOMP_BPF: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/seccomp.bpf"));
 pub const WRAP_SH: &[u8] = include_bytes!("../res/wrapper/security-wrapper.sh");
 pub const WRAP_ARGS_EXAMPLE: &[u8] = include_bytes!("../res/wrapper/wrap_args.sh.example");
+pub const TAR_CHECK_POLICY_EXAMPLE: &[u8] = include_bytes!("../res/tar_check_policy.toml.example");
 pub const CONFIG_LOADER: &[u8] = include_bytes!("../res/print_makepkg_config.sh");
 
 pub const WRAP_SCRIPT_PATH: &str = ".system/security-wrapper.sh";
@@ -0,0 +1,301 @@
+use colored::Colorize;
+use std::fmt;
+use tar::EntryType;
+
+/// Archive entries that can grant privileges or weaken the system, other than executables
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpecialFile {
+	Suid,
+	Sgid,
+	/// File capabilities from the `security.capability` xattr, like `cap_net_raw=ep`
+	Capabilities(String),
+	/// Writable by anyone. Directories with the sticky bit are marked as such.
+	WorldWritable {
+		sticky: bool,
+	},
+	/// Character or block device
+	DeviceNode,
+	/// Symlink to a sensitive location on the installed system, with the resolved target
+	SensitiveSymlink(String),
+}
+
+impl SpecialFile {
+	/// Privileged files are those that can run with more rights than the user has
+	pub fn is_privileged(&self) -> bool {
+		matches!(
+			self,
+			SpecialFile::Suid | SpecialFile::Sgid | SpecialFile::Capabilities(_)
+		)
+	}
+}
+
+impl fmt::Display for SpecialFile {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SpecialFile::Suid => write!(f, "{}", "SUID".bold().bright_red()),
+			SpecialFile::Sgid => write!(f, "{}", "SGID".bold().bright_red()),
+			SpecialFile::Capabilities(caps) => {
+				write!(f, "{} {}", "capabilities".bold().bright_red(), caps)
+			}
+			SpecialFile::WorldWritable { sticky: false } => {
+				write!(f, "{}", "world-writable".bold().bright_red())
+			}
+			SpecialFile::WorldWritable { sticky: true } => {
+				write!(f, "{}", "world-writable (sticky)".yellow())
+			}
+			SpecialFile::DeviceNode => write!(f, "{}", "device node".bold().bright_red()),
+			SpecialFile::SensitiveSymlink(target) => {
+				write!(
+					f,
+					"{} -> /{}",
+					"sensitive symlink".bold().bright_red(),
+					target
+				)
+			}
+		}
+	}
+}
+
+/// Locations that a package has no business linking to, relative to the root directory.
+/// Symlinks to these can expose secrets, or redirect writes of other programs.
+const SENSITIVE_PATHS: [&str; 15] = [
+	"etc/shadow",
+	"etc/gshadow",
+	"etc/passwd",
+	"etc/group",
+	"etc/sudoers",
+	"etc/sudoers.d",
+	"etc/ssh",
+	"etc/pam.d",
+	"etc/pacman.conf",
+	"etc/pacman.d",
+	"var/lib/pacman",
+	"root",
+	// Links to /dev/null and /proc/self/mounts are common, raw memory access is not
+	"dev/mem",
+	"dev/kmem",
+	"dev/port",
+];
+
+/// Pax header keys of the `security.capability` xattr, as written by GNU tar/star and libarchive
+const CAPABILITY_XATTRS: [&str; 2] = [
+	"SCHILY.xattr.security.capability",
+	"LIBARCHIVE.xattr.security.capability",
+];
+
+pub fn is_capability_xattr(pax_key: &str) -> bool {
+	CAPABILITY_XATTRS.contains(&pax_key)
+}
+
+/// Classifies an archive entry.
+/// `capability` is the raw `security.capability` xattr, and `link_target` is the symlink target.
+pub fn classify(
+	path: &str,
+	entry_type: EntryType,
+	mode: u32,
+	link_target: Option<&str>,
+	capability: Option<&[u8]>,
+) -> Vec<SpecialFile> {
+	let mut result = Vec::new();
+	if entry_type.is_symlink() {
+		let resolved = link_target.map(|target| resolve_symlink(path, target));
+		if let Some(resolved) = resolved.filter(|resolved| is_sensitive(resolved)) {
+			result.push(SpecialFile::SensitiveSymlink(resolved));
+		}
+		// Permissions of symlinks are not used
+		return result;
+	}
+	if mode & 0o4000 != 0 {
+		result.push(SpecialFile::Suid);
+	}
+	if mode & 0o2000 != 0 {
+		result.push(SpecialFile::Sgid);
+	}
+	if let Some(capability) = capability {
+		let caps = describe_capabilities(capability).unwrap_or_else(|| "(unparsed)".to_string());
+		result.push(SpecialFile::Capabilities(caps));
+	}
+	if mode & 0o002 != 0 {
+		let sticky = entry_type.is_dir() && mode & 0o1000 != 0;
+		result.push(SpecialFile::WorldWritable { sticky });
+	}
+	if entry_type.is_character_special() || entry_type.is_block_special() {
+		result.push(SpecialFile::DeviceNode);
+	}
+	result
+}
+
+fn is_sensitive(resolved: &str) -> bool {
+	resolved.is_empty()
+		|| SENSITIVE_PATHS.iter().any(|sensitive| {
+			resolved == *sensitive
+				|| resolved
+					.strip_prefix(sensitive)
+					.map_or(false, |rest| rest.starts_with('/'))
+		})
+}
+
+/// Resolves a symlink target lexically, as it would be on the installed system.
+/// The result is relative to the root directory, `..` does not go above the root.
+fn resolve_symlink(path: &str, target: &str) -> String {
+	let mut components: Vec<&str> = if target.starts_with('/') {
+		Vec::new()
+	} else {
+		let path = path.trim_end_matches('/');
+		let parent = path.rsplit_once('/').map_or("", |(parent, _name)| parent);
+		parent.split('/').filter(|c| !c.is_empty()).collect()
+	};
+	for component in target.split('/') {
+		match component {
+			"" | "." => {}
+			".." => {
+				components.pop();
+			}
+			component => components.push(component),
+		}
+	}
+	components.join("/")
+}
+
+/// Names of the capabilities by bit number, see capabilities(7)
+const CAPABILITY_NAMES: [&str; 41] = [
+	"cap_chown",
+	"cap_dac_override",
+	"cap_dac_read_search",
+	"cap_fowner",
+	"cap_fsetid",
+	"cap_kill",
+	"cap_setgid",
+	"cap_setuid",
+	"cap_setpcap",
+	"cap_linux_immutable",
+	"cap_net_bind_service",
+	"cap_net_broadcast",
+	"cap_net_admin",
+	"cap_net_raw",
+	"cap_ipc_lock",
+	"cap_ipc_owner",
+	"cap_sys_module",
+	"cap_sys_rawio",
+	"cap_sys_chroot",
+	"cap_sys_ptrace",
+	"cap_sys_pacct",
+	"cap_sys_admin",
+	"cap_sys_boot",
+	"cap_sys_nice",
+	"cap_sys_resource",
+	"cap_sys_time",
+	"cap_sys_tty_config",
+	"cap_mknod",
+	"cap_lease",
+	"cap_audit_write",
+	"cap_audit_control",
+	"cap_setfcap",
+	"cap_mac_override",
+	"cap_mac_admin",
+	"cap_syslog",
+	"cap_wake_alarm",
+	"cap_block_suspend",
+	"cap_audit_read",
+	"cap_perfmon",
+	"cap_bpf",
+	"cap_checkpoint_restore",
+];
+
+/// Decodes `struct vfs_cap_data` into the `getcap` notation, like `cap_net_raw=ep`
+fn describe_capabilities(xattr: &[u8]) -> Option<String> {
+	let word = |index: usize| -> Option<u64> {
+		let bytes = xattr.get(index * 4..index * 4 + 4)?;
+		Some(u32::from_le_bytes(bytes.try_into().ok()?).into())
+	};
+	let magic = word(0)?;
+	let effective = magic & 1 != 0;
+	let permitted = match magic & 0xff00_0000 {
+		0x0100_0000 => word(1)?,
+		0x0200_0000 | 0x0300_0000 => word(1)? | (word(3)? << 32),
+		_ => return None,
+	};
+	let names: Vec<String> = (0..64)
+		.filter(|bit| permitted & (1 << bit) != 0)
+		.map(|bit| match CAPABILITY_NAMES.get(bit) {
+			Some(name) => name.to_string(),
+			None => format!("cap_{}", bit),
+		})
+		.collect();
+	let flags = if effective { "ep" } else { "p" };
+	Some(format!("{}={}", names.join(","), flags))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::special_files::*;
+
+	#[test]
+	fn test_classify() {
+		let classify_file =
+			|mode: u32| classify("usr/bin/foo", EntryType::Regular, mode, None, None);
+		assert_eq!(classify_file(0o755), []);
+		assert_eq!(
+			classify_file(0o6755),
+			[SpecialFile::Suid, SpecialFile::Sgid]
+		);
+		assert_eq!(
+			classify_file(0o777),
+			[SpecialFile::WorldWritable { sticky: false }]
+		);
+		assert_eq!(
+			classify("tmp/foo/", EntryType::Directory, 0o1777, None, None),
+			[SpecialFile::WorldWritable { sticky: true }]
+		);
+		assert_eq!(
+			classify("dev/foo", EntryType::Char, 0o600, None, None),
+			[SpecialFile::DeviceNode]
+		);
+		let symlink = |target: &str| {
+			classify(
+				"usr/share/foo/passwd",
+				EntryType::Symlink,
+				0o777,
+				Some(target),
+				None,
+			)
+		};
+		assert_eq!(symlink("../../lib/foo/passwd"), []);
+		assert_eq!(
+			symlink("../../../../../etc/shadow"),
+			[SpecialFile::SensitiveSymlink("etc/shadow".to_string())]
+		);
+		assert_eq!(
+			symlink("/etc/sudoers.d/foo"),
+			[SpecialFile::SensitiveSymlink(
+				"etc/sudoers.d/foo".to_string()
+			)]
+		);
+		assert_eq!(symlink("/etc/shadowsocks"), []);
+		assert_eq!(symlink("/"), [SpecialFile::SensitiveSymlink(String::new())]);
+	}
+
+	#[test]
+	fn test_capabilities() {
+		// `setcap cap_net_raw,cap_net_admin=ep`, revision 2
+		let mut xattr = Vec::new();
+		for word in [0x0200_0001_u32, (1 << 12) | (1 << 13), 0, 0, 0] {
+			xattr.extend_from_slice(&word.to_le_bytes());
+		}
+		assert_eq!(
+			classify(
+				"usr/bin/ping",
+				EntryType::Regular,
+				0o755,
+				None,
+				Some(&xattr)
+			),
+			[SpecialFile::Capabilities(
+				"cap_net_admin,cap_net_raw=ep".to_string()
+			)]
+		);
+		assert_eq!(describe_capabilities(&[1, 2]), None);
+		assert!(is_capability_xattr("SCHILY.xattr.security.capability"));
+		assert!(!is_capability_xattr("SCHILY.xattr.user.comment"));
+	}
+}
@@ -0,0 +1,277 @@
+use colored::Colorize;
+use indexmap::IndexMap;
+use indexmap::IndexSet;
+use srcinfo::ArchVecs;
+use srcinfo::Srcinfo;
+use std::fmt;
+
+/// Security-relevant changes between the reviewed and the incoming .SRCINFO
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SrcinfoDiff {
+	/// Old and new version, if changed
+	pub version: Option<(String, String)>,
+	pub added_sources: Vec<String>,
+	pub removed_sources: Vec<String>,
+	/// Domains that sources are downloaded from, which were not used before
+	pub added_domains: Vec<String>,
+	pub removed_domains: Vec<String>,
+	/// Sources whose checksum was verified before, but is SKIP now
+	pub skipped_checksums: Vec<String>,
+	pub added_pgp_keys: Vec<String>,
+}
+
+impl SrcinfoDiff {
+	/// Compares the two .SRCINFO files. Without `old`, everything in `new` is reported as added.
+	pub fn new(old: Option<&Srcinfo>, new: &Srcinfo) -> SrcinfoDiff {
+		let old_sources = old.map(sources).unwrap_or_default();
+		let new_sources = sources(new);
+		let old_domains: IndexSet<&str> = old_sources.keys().filter_map(|s| domain(s)).collect();
+		let new_domains: IndexSet<&str> = new_sources.keys().filter_map(|s| domain(s)).collect();
+		let old_version = old.map(Srcinfo::version);
+		let version = match old_version {
+			Some(old_version) if old_version != new.version() => Some((old_version, new.version())),
+			_ => None,
+		};
+		let skipped_checksums = new_sources
+			.iter()
+			.filter(|(source, new_sums)| {
+				let old_sums = old_sources.get(*source);
+				new_sums.iter().any(|(kind, sum)| {
+					sum == "SKIP"
+						&& old_sums
+							.and_then(|old_sums| old_sums.get(kind))
+							.map_or(false, |old_sum| old_sum != "SKIP")
+				})
+			})
+			.map(|(source, _)| source.to_string())
+			.collect();
+		let old_keys = old.map_or(&[][..], |old| &old.base.valid_pgp_keys);
+		SrcinfoDiff {
+			version,
+			added_sources: difference(new_sources.keys(), old_sources.keys()),
+			removed_sources: difference(old_sources.keys(), new_sources.keys()),
+			added_domains: difference(new_domains.iter(), old_domains.iter()),
+			removed_domains: difference(old_domains.iter(), new_domains.iter()),
+			skipped_checksums,
+			added_pgp_keys: difference(new.base.valid_pgp_keys.iter(), old_keys.iter()),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self == &SrcinfoDiff::default()
+	}
+}
+
+impl fmt::Display for SrcinfoDiff {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some((old, new)) = &self.version {
+			writeln!(f, "  version {} -> {}", old, new.green())?;
+		}
+		let lists = [
+			("new source", &self.added_sources, true),
+			("removed source", &self.removed_sources, false),
+			("new domain", &self.added_domains, true),
+			("removed domain", &self.removed_domains, false),
+			("checksum changed to SKIP", &self.skipped_checksums, true),
+			("new PGP key", &self.added_pgp_keys, true),
+		];
+		for (label, values, important) in lists {
+			for value in values {
+				let label = if important {
+					label.bold().yellow()
+				} else {
+					label.normal()
+				};
+				writeln!(f, "  {}: {}", label, value)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Differences between the committed .SRCINFO and the one generated from PKGBUILD.
+/// These are expected to be identical, otherwise the reviewed .SRCINFO is not what gets built.
+pub fn mismatches(committed: &Srcinfo, generated: &Srcinfo) -> Vec<String> {
+	let mut result = Vec::new();
+	if committed.version() != generated.version() {
+		result.push(format!(
+			"version is {} in .SRCINFO, but {} in PKGBUILD",
+			committed.version(),
+			generated.version()
+		));
+	}
+	let committed_names: Vec<&str> = committed.pkgnames().collect();
+	let generated_names: Vec<&str> = generated.pkgnames().collect();
+	if committed_names != generated_names {
+		result.push(format!(
+			"packages are {:?} in .SRCINFO, but {:?} in PKGBUILD",
+			committed_names, generated_names
+		));
+	}
+	let committed_sources = sources(committed);
+	let generated_sources = sources(generated);
+	for source in difference(generated_sources.keys(), committed_sources.keys()) {
+		result.push(format!("source {} is only in PKGBUILD", source));
+	}
+	for source in difference(committed_sources.keys(), generated_sources.keys()) {
+		result.push(format!("source {} is only in .SRCINFO", source));
+	}
+	for (source, sums) in &generated_sources {
+		match committed_sources.get(source) {
+			Some(committed_sums) if committed_sums != sums => {
+				result.push(format!("checksums of {} differ", source));
+			}
+			_ => {}
+		}
+	}
+	if committed.base.valid_pgp_keys != generated.base.valid_pgp_keys {
+		result.push(format!(
+			"validpgpkeys are {:?} in .SRCINFO, but {:?} in PKGBUILD",
+			committed.base.valid_pgp_keys, generated.base.valid_pgp_keys
+		));
+	}
+	result
+}
+
+fn difference<'a>(
+	from: impl Iterator<Item = &'a (impl AsRef<str> + 'a + ?Sized)>,
+	remove: impl Iterator<Item = &'a (impl AsRef<str> + 'a + ?Sized)>,
+) -> Vec<String> {
+	let remove: IndexSet<&str> = remove.map(AsRef::as_ref).collect();
+	from.map(AsRef::as_ref)
+		.filter(|value| !remove.contains(value))
+		.map(str::to_string)
+		.collect()
+}
+
+/// Sources with their checksums, by checksum kind.
+/// Architecture-specific sources are prefixed with the architecture, as in `x86_64: foo.tar.gz`.
+fn sources(srcinfo: &Srcinfo) -> IndexMap<String, IndexMap<&'static str, String>> {
+	let base = &srcinfo.base;
+	let checksums: [(&'static str, &ArchVecs); 7] = [
+		("md5sums", &base.md5sums),
+		("sha1sums", &base.sha1sums),
+		("sha224sums", &base.sha224sums),
+		("sha256sums", &base.sha256sums),
+		("sha384sums", &base.sha384sums),
+		("sha512sums", &base.sha512sums),
+		("b2sums", &base.b2sums),
+	];
+	let mut result = IndexMap::new();
+	for sources in &base.source {
+		for (index, source) in sources.values().iter().enumerate() {
+			let sums = checksums
+				.iter()
+				.filter_map(|(kind, arch_vecs)| {
+					let arch_vec = arch_vecs.iter().find(|v| v.arch() == sources.arch())?;
+					Some((*kind, arch_vec.values().get(index)?.to_string()))
+				})
+				.collect();
+			let key = match sources.arch() {
+				Some(arch) => format!("{}: {}", arch, source),
+				None => source.to_string(),
+			};
+			result.insert(key, sums);
+		}
+	}
+	result
+}
+
+/// Domain of a remote source, like `example.org` in `foo.tar.gz::git+https://example.org/foo`
+fn domain(source: &str) -> Option<&str> {
+	let url = source.rsplit_once("::").map_or(source, |(_, url)| url);
+	let (_, rest) = url.split_once("://")?;
+	let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+	let host = &rest[..end];
+	// user@host:port
+	let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+	Some(host.split(':').next().unwrap_or(host))
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::srcinfo_diff::*;
+	use std::str::FromStr;
+
+	fn srcinfo(version: &str, extra: &str) -> Srcinfo {
+		let content = format!(
+			"pkgbase = foo\n\tpkgver = {}\n\tpkgrel = 1\n\tarch = x86_64\n{}\npkgname = foo\n",
+			version, extra
+		);
+		Srcinfo::from_str(&content).unwrap()
+	}
+
+	#[test]
+	fn test_srcinfo_diff() {
+		let old = srcinfo(
+			"1.0",
+			"\tsource = foo.tar.gz::https://example.org/foo-1.0.tar.gz
+	source = fix.patch
+	sha256sums = 0123
+	sha256sums = 4567
+	validpgpkeys = AAAA",
+		);
+		let new = srcinfo(
+			"1.1",
+			"\tsource = foo.tar.gz::https://example.org/foo-1.0.tar.gz
+	source = fix.patch
+	source = https://evil.example.com:8080/payload
+	sha256sums = SKIP
+	sha256sums = 4567
+	sha256sums = 89ab
+	validpgpkeys = AAAA
+	validpgpkeys = BBBB",
+		);
+		let diff = SrcinfoDiff::new(Some(&old), &new);
+		assert_eq!(
+			diff,
+			SrcinfoDiff {
+				version: Some(("1.0-1".to_string(), "1.1-1".to_string())),
+				added_sources: vec!["https://evil.example.com:8080/payload".to_string()],
+				removed_sources: vec![],
+				added_domains: vec!["evil.example.com".to_string()],
+				removed_domains: vec![],
+				skipped_checksums: vec![
+					"foo.tar.gz::https://example.org/foo-1.0.tar.gz".to_string()
+				],
+				added_pgp_keys: vec!["BBBB".to_string()],
+			}
+		);
+		assert!(SrcinfoDiff::new(Some(&new), &new).is_empty());
+	}
+
+	#[test]
+	fn test_mismatches() {
+		let committed = srcinfo(
+			"1.0",
+			"\tsource = https://example.org/foo-1.0.tar.gz\n\tsha256sums = 0123",
+		);
+		let generated = srcinfo(
+			"1.0",
+			"\tsource = https://example.org/foo-1.0.tar.gz\n\tsource = https://evil.example.com/x\n\tsha256sums = 0124\n\tsha256sums = SKIP",
+		);
+		assert_eq!(
+			mismatches(&committed, &generated),
+			[
+				"source https://evil.example.com/x is only in PKGBUILD",
+				"checksums of https://example.org/foo-1.0.tar.gz differ",
+			]
+		);
+		assert!(mismatches(&generated, &generated).is_empty());
+	}
+
+	#[test]
+	fn test_srcinfo_diff_without_old() {
+		let new = srcinfo(
+			"1.0",
+			"\tsource_x86_64 = git+https://example.org/foo.git#tag=1.0",
+		);
+		let diff = SrcinfoDiff::new(None, &new);
+		assert_eq!(
+			diff.added_sources,
+			["x86_64: git+https://example.org/foo.git#tag=1.0"]
+		);
+		assert_eq!(diff.added_domains, ["example.org"]);
+		assert_eq!(diff.version, None);
+	}
+}
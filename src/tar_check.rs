@@ -1,39 +1,73 @@
+use crate::alpm_wrapper::new_alpm_wrapper;
+use crate::alpm_wrapper::AlpmWrapper;
+use crate::noconfirm;
+use crate::noconfirm::NoConfirm;
+use crate::package_metadata;
+use crate::package_metadata::FileDigest;
+use crate::package_metadata::PackageInfo;
+use crate::rua_paths;
+use crate::special_files;
+use crate::special_files::SpecialFile;
+use crate::tar_check_policy::Rules;
+use crate::tar_check_policy::Severity;
+use crate::tar_check_policy::TarCheckPolicy;
 use crate::terminal_util;
 extern crate libflate;
 extern crate ruzstd;
 use colored::*;
+use indexmap::IndexMap;
 use indexmap::IndexSet;
 use libflate::gzip::Decoder;
 use log::debug;
 use ruzstd::decoding::StreamingDecoder;
+use sha2::Digest;
+use sha2::Sha256;
+use srcinfo::Srcinfo;
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use tar::*;
 use xz2::read::XzDecoder;
 
-pub fn tar_check_unwrap(tar_file: &Path, file_name: &str) {
-	let result = tar_check(tar_file, file_name);
+/// Checks a built package archive, comparing its metadata with `srcinfo` if given
+pub fn tar_check_unwrap(
+	tar_file: &Path,
+	file_name: &str,
+	srcinfo: Option<&Srcinfo>,
+	noconfirm: Option<&NoConfirm>,
+) {
+	let result = tar_check(tar_file, file_name, srcinfo, noconfirm);
 	result.unwrap_or_else(|err| {
 		eprintln!("{}", err);
 		std::process::exit(1)
 	})
 }
 
-pub fn tar_check(tar_file: &Path, tar_str: &str) -> Result<(), String> {
+pub fn tar_check(
+	tar_file: &Path,
+	tar_str: &str,
+	srcinfo: Option<&Srcinfo>,
+	noconfirm: Option<&NoConfirm>,
+) -> Result<(), String> {
 	let archive = File::open(tar_file).unwrap_or_else(|_| panic!("cannot open file {}", tar_str));
 	debug!("Checking file {}", tar_str);
 	if tar_str.ends_with(".tar") {
-		tar_check_archive(Archive::new(archive), tar_str);
+		tar_check_archive(Archive::new(archive), tar_str, srcinfo, noconfirm);
 		Ok(())
 	} else if tar_str.ends_with(".tar.xz") || tar_str.ends_with(".tar.lzma") {
-		tar_check_archive(Archive::new(XzDecoder::new(archive)), tar_str);
+		tar_check_archive(
+			Archive::new(XzDecoder::new(archive)),
+			tar_str,
+			srcinfo,
+			noconfirm,
+		);
 		Ok(())
 	} else if tar_str.ends_with(".tar.gz") || tar_str.ends_with(".tar.gzip") {
 		match Decoder::new(archive) {
 			Ok(decoded) => {
-				tar_check_archive(Archive::new(decoded), tar_str);
+				tar_check_archive(Archive::new(decoded), tar_str, srcinfo, noconfirm);
 				Ok(())
 			},
 			Err(err) => {
@@ -44,7 +78,7 @@ pub fn tar_check(tar_file: &Path, tar_str: &str) -> Result<(), String> {
 		let mut archive = archive;
 		match StreamingDecoder::new(&mut archive) {
 			Ok(decoder) => {
-				tar_check_archive(Archive::new(decoder), tar_str);
+				tar_check_archive(Archive::new(decoder), tar_str, srcinfo, noconfirm);
 				Ok(())
 			},
 			Err(err) => {
@@ -59,11 +93,23 @@ pub fn tar_check(tar_file: &Path, tar_str: &str) -> Result<(), String> {
 	}
 }
 
-fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
+fn tar_check_archive<R: Read>(
+	mut archive: Archive<R>,
+	path_str: &str,
+	srcinfo: Option<&Srcinfo>,
+	noconfirm: Option<&NoConfirm>,
+) {
 	let mut install_file = String::new();
+	let mut pkginfo_file = String::new();
+	let mut buildinfo_file = String::new();
+	let mut mtree_file = None;
+	let mut digests = IndexMap::new();
+	let mut hard_links = Vec::new();
 	let mut all_files = Vec::new();
 	let mut executable_files = Vec::new();
 	let mut suid_files = Vec::new();
+	let mut special_files = Vec::new();
+	let mut total_size = 0;
 	let archive_files = archive
 		.entries()
 		.unwrap_or_else(|e| panic!("cannot open archive {}, {}", path_str, e));
@@ -89,6 +135,7 @@ fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
 				path
 			)
 		});
+		total_size += file.header().entry_size().unwrap_or(0);
 		let is_normal = !path.ends_with('/') && !path.starts_with('.');
 		if is_normal {
 			all_files.push(path.clone());
@@ -96,17 +143,175 @@ fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
 		if is_normal && (mode & 0o111 > 0) {
 			executable_files.push(path.clone());
 		}
-		if mode > 0o777 {
+		let entry_type = file.header().entry_type();
+		let link_target = file.header().link_name().ok().flatten();
+		let link_target = link_target.map(|target| target.to_string_lossy().into_owned());
+		let capability = capability_xattr(&mut file);
+		let kinds = special_files::classify(
+			&path,
+			entry_type,
+			mode,
+			link_target.as_deref(),
+			capability.as_deref(),
+		);
+		if kinds.iter().any(SpecialFile::is_privileged) {
 			suid_files.push(path.clone());
 		}
-		if &path == ".INSTALL" {
-			file.read_to_string(&mut install_file).unwrap_or_else(|_| {
-				panic!("Failed to read INSTALL script from tar file {}", path_str)
+		special_files.extend(kinds.into_iter().map(|kind| (path.clone(), kind)));
+		if entry_type.is_hard_link() {
+			if let Some(target) = link_target {
+				hard_links.push((path.clone(), target));
+			}
+		} else if entry_type.is_file() && path.starts_with('.') {
+			let mut content = Vec::new();
+			file.read_to_end(&mut content).unwrap_or_else(|e| {
+				panic!("Failed to read {} from tar file {}, {}", path, path_str, e)
 			});
+			digests.insert(path.clone(), FileDigest::of_bytes(&content));
+			let text = || String::from_utf8_lossy(&content).into_owned();
+			match path.as_str() {
+				".INSTALL" => install_file = text(),
+				".PKGINFO" => pkginfo_file = text(),
+				".BUILDINFO" => buildinfo_file = text(),
+				".MTREE" => mtree_file = Some(decompress_mtree(&content, path_str)),
+				_ => {}
+			}
+		} else if entry_type.is_file() {
+			let mut hasher = Sha256::new();
+			let size = io::copy(&mut file, &mut hasher).unwrap_or_else(|e| {
+				panic!("Failed to read {} from tar file {}, {}", path, path_str, e)
+			});
+			digests.insert(path.clone(), FileDigest::new(size, hasher));
+		}
+	}
+	for (path, target) in hard_links {
+		if let Some(digest) = digests.get(&target).cloned() {
+			digests.insert(path, digest);
 		}
 	}
 
 	let has_install = !install_file.is_empty();
+	let pkginfo = PackageInfo::parse(&pkginfo_file);
+	let buildinfo = PackageInfo::parse(&buildinfo_file);
+	let pkgname = pkginfo.first("pkgname").unwrap_or("");
+	let mtree_problems = match &mtree_file {
+		Some(mtree) => {
+			package_metadata::verify_mtree(&package_metadata::parse_mtree(mtree), &digests)
+		}
+		None => vec!["package has no .MTREE, file digests cannot be verified".to_string()],
+	};
+	if !mtree_problems.is_empty() {
+		eprintln!(
+			"{}",
+			format!("Package {} does not match its .MTREE:", path_str)
+				.bold()
+				.red()
+		);
+		for problem in &mtree_problems {
+			eprintln!("  {}", problem);
+		}
+	}
+	let srcinfo_mismatches = srcinfo.map_or_else(Vec::new, |srcinfo| {
+		package_metadata::srcinfo_mismatches(&pkginfo, srcinfo)
+	});
+	if !srcinfo_mismatches.is_empty() {
+		eprintln!(
+			"{}",
+			format!(
+				"Package {} metadata differs from the reviewed .SRCINFO:",
+				path_str
+			)
+			.yellow()
+		);
+		for mismatch in &srcinfo_mismatches {
+			eprintln!("  {}", mismatch);
+		}
+	}
+	let replaceable = replaceable_owners(&pkginfo, srcinfo);
+	let conflicts = file_conflicts(&all_files, &replaceable, &*new_alpm_wrapper(), |path| {
+		Path::new("/").join(path).symlink_metadata().is_ok()
+	});
+	let conflicts = conflicts.unwrap_or_else(|err| {
+		let reason = format!(
+			"cannot check package {} for file conflicts, {}",
+			path_str, err
+		);
+		if noconfirm.is_some() {
+			noconfirm::reject(noconfirm::EXIT_CONFLICT_CHECK_FAILED, &reason);
+		}
+		eprintln!("{} {}", "WARNING:".bold().red(), reason);
+		eprint!("Continue without knowing which files it would overwrite? [y/N] ");
+		if terminal_util::read_line_lowercase() != "y" {
+			eprintln!("Exiting...");
+			std::process::exit(1);
+		}
+		FileConflicts::default()
+	});
+	if !conflicts.is_empty() {
+		eprintln!(
+			"{}",
+			format!(
+				"Package {} would overwrite {} files of other packages and {} unowned files, see [C]",
+				path_str,
+				conflicts.owned.len(),
+				conflicts.unowned.len()
+			)
+			.bold()
+			.bright_red()
+		);
+	}
+	let rules = TarCheckPolicy::load(&rua_paths::tar_check_policy_path()).rules(pkgname);
+	let violations = rules.evaluate(&all_files, &suid_files, total_size, has_install);
+	let blocked = violations.iter().any(|v| v.severity == Severity::Block);
+	if !violations.is_empty() {
+		eprintln!("Package {} violates the tar_check policy:", path_str);
+		for violation in &violations {
+			eprintln!("  {}", violation);
+		}
+	}
+	if let Some(noconfirm) = noconfirm {
+		if blocked {
+			let reason = format!("package {} violates a blocking policy rule", path_str);
+			noconfirm::reject(noconfirm::EXIT_POLICY_VIOLATION, &reason);
+		}
+		let rejection = noconfirm_rejection(
+			path_str,
+			pkgname,
+			&suid_files,
+			has_install,
+			noconfirm,
+			&rules,
+		);
+		if let Some((exit_code, reason)) = rejection {
+			noconfirm::reject(exit_code, &reason);
+		}
+		for (path, kind) in special_files
+			.iter()
+			.filter(|(_, kind)| !kind.is_privileged())
+		{
+			eprintln!("{} {}", kind, path);
+		}
+		eprintln!(
+			"Package {} has no SUID files and no unexpected install script.",
+			path_str
+		);
+		return;
+	}
+	let unprivileged_special_files = special_files
+		.iter()
+		.filter(|(_, kind)| !kind.is_privileged())
+		.count();
+	if unprivileged_special_files > 0 {
+		eprintln!(
+			"{}",
+			format!(
+				"Package {} has {} world-writable files, device nodes or sensitive symlinks, see [S]",
+				path_str, unprivileged_special_files
+			)
+			.bold()
+			.bright_red()
+		);
+	}
 	loop {
 		if suid_files.is_empty() {
 			eprintln!("Package {} has no SUID files.", path_str);
@@ -129,19 +334,47 @@ fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
 			);
 		};
 
-		if !suid_files.is_empty() {
+		if !special_files.is_empty() {
 			eprint!(
 				"{}=list {}, ",
 				"[S]".bold(),
-				"SUID files".bold().bright_red()
+				"SUID and special files".bold().bright_red()
 			);
 		};
-		eprint!("{}=ok, proceed. ", "[O]".bold());
+		if !conflicts.is_empty() {
+			eprint!(
+				"{}=list {}, ",
+				"[C]".bold(),
+				"conflicting files".bold().bright_red()
+			);
+		}
+		if !pkginfo.is_empty() {
+			eprint!("{}=show package metadata, ", "[M]".bold());
+		}
+		if !buildinfo.is_empty() {
+			eprint!("{}=show build environment, ", "[B]".bold());
+		}
+		if !violations.is_empty() {
+			eprint!(
+				"{}=show {}, ",
+				"[P]".bold(),
+				"policy violations".bold().bright_red()
+			);
+		}
+		if blocked {
+			eprint!(
+				"{}=quit ({}). ",
+				"[Q]".bold(),
+				"blocked by policy".bold().red()
+			);
+		} else {
+			eprint!("{}=ok, proceed. ", "[O]".bold());
+		}
 		let string = terminal_util::read_line_lowercase();
 		eprintln!();
-		if &string == "s" && !suid_files.is_empty() {
-			for path in &suid_files {
-				eprintln!("{}", path);
+		if &string == "s" && !special_files.is_empty() {
+			for (path, kind) in &special_files {
+				eprintln!("{} {}", kind, path);
 			}
 		} else if &string == "e" {
 			for path in &executable_files {
@@ -164,6 +397,30 @@ fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
 			let dir = dir.parent().unwrap_or_else(|| Path::new("."));
 			eprintln!("Exit the shell with `logout` or Ctrl-D...");
 			terminal_util::run_env_command(dir, "SHELL", "bash", &[]);
+		} else if &string == "c" && !conflicts.is_empty() {
+			for (path, owner) in &conflicts.owned {
+				eprintln!("{} {}", path, format!("(owned by {})", owner).bright_red());
+			}
+			for path in &conflicts.unowned {
+				eprintln!("{} {}", path, "(exists, not owned by any package)".yellow());
+			}
+		} else if &string == "m" && !pkginfo.is_empty() {
+			eprint!("{}", pkginfo);
+			for mismatch in &srcinfo_mismatches {
+				eprintln!("{} {}", "differs from .SRCINFO:".yellow(), mismatch);
+			}
+		} else if &string == "b" && !buildinfo.is_empty() {
+			eprint!("{}", buildinfo);
+		} else if &string == "p" && !violations.is_empty() {
+			for violation in &violations {
+				eprintln!("{}", violation);
+			}
+		} else if &string == "o" && blocked {
+			eprintln!(
+				"Package {} cannot be installed, it violates a blocking rule of the policy in {:?}",
+				path_str,
+				rua_paths::tar_check_policy_path()
+			);
 		} else if &string == "o" {
 			break;
 		} else if &string == "q" {
@@ -173,6 +430,126 @@ fn tar_check_archive<R: Read>(mut archive: Archive<R>, path_str: &str) {
 	}
 }
 
+/// Files of the archive that already exist on the system, and do not belong to the package itself
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FileConflicts {
+	/// Files owned by other installed packages, with the owner
+	owned: Vec<(String, String)>,
+	/// Existing files not owned by any package
+	unowned: Vec<String>,
+}
+
+impl FileConflicts {
+	fn is_empty(&self) -> bool {
+		self.owned.is_empty() && self.unowned.is_empty()
+	}
+}
+
+/// Installed packages whose files the package is expected to take over:
+/// the package itself, other packages of its pkgbase, and those it replaces or conflicts with
+fn replaceable_owners(pkginfo: &PackageInfo, srcinfo: Option<&Srcinfo>) -> IndexSet<String> {
+	let siblings = srcinfo
+		.iter()
+		.flat_map(|srcinfo| srcinfo.pkgs.iter().map(|pkg| pkg.pkgname.as_str()));
+	let declared = ["pkgname", "replaces", "conflict"]
+		.iter()
+		.flat_map(|key| pkginfo.get(key))
+		.map(|entry| entry.split(['<', '>', '=']).next().unwrap_or(entry));
+	siblings.chain(declared).map(str::to_string).collect()
+}
+
+/// Only files that exist on the system are looked up in the pacman database.
+/// Fails if the database cannot be queried, which must not pass for a lack of conflicts.
+fn file_conflicts(
+	files: &[String],
+	replaceable: &IndexSet<String>,
+	alpm: &dyn AlpmWrapper,
+	exists: impl Fn(&str) -> bool,
+) -> Result<FileConflicts, String> {
+	let files: Vec<String> = files.iter().filter(|file| exists(file)).cloned().collect();
+	let owners = alpm
+		.file_owners(&files)
+		.map_err(|err| format!("failed to query the pacman database, {:#}", err))?;
+	let mut result = FileConflicts::default();
+	for file in files {
+		match owners.get(&file) {
+			Some(owner) if replaceable.contains(owner) => {}
+			Some(owner) => result.owned.push((file, owner.to_string())),
+			None => result.unowned.push(file),
+		}
+	}
+	Ok(result)
+}
+
+/// Raw `security.capability` xattr of the entry, preferring the unencoded GNU tar/star format
+fn capability_xattr<R: Read>(file: &mut Entry<R>) -> Option<Vec<u8>> {
+	let extensions = file.pax_extensions().ok().flatten()?;
+	extensions
+		.filter_map(Result::ok)
+		.filter(|extension| {
+			extension
+				.key()
+				.map_or(false, special_files::is_capability_xattr)
+		})
+		.min_by_key(|extension| {
+			!extension
+				.key()
+				.map_or(false, |key| key.starts_with("SCHILY."))
+		})
+		.map(|extension| extension.value_bytes().to_vec())
+}
+
+/// `.MTREE` is gzip-compressed by makepkg
+fn decompress_mtree(content: &[u8], path_str: &str) -> String {
+	if content.starts_with(b"#mtree") {
+		return String::from_utf8_lossy(content).into_owned();
+	}
+	let mut result = String::new();
+	Decoder::new(content)
+		.and_then(|mut decoder| decoder.read_to_string(&mut result))
+		.unwrap_or_else(|e| {
+			panic!(
+				"Failed to decompress .MTREE from tar file {}, {}",
+				path_str, e
+			)
+		});
+	result
+}
+
+/// Decides on the archive without asking the user, returning the exit code and the reason to reject it
+fn noconfirm_rejection(
+	path_str: &str,
+	pkgname: &str,
+	suid_files: &[String],
+	has_install: bool,
+	noconfirm: &NoConfirm,
+	rules: &Rules,
+) -> Option<(i32, String)> {
+	let suid_files: Vec<&str> = suid_files
+		.iter()
+		.map(String::as_str)
+		.filter(|file| !rules.allows_suid(file))
+		.collect();
+	let install_script_allowed = noconfirm.allows_install_script(pkgname)
+		|| rules.install_script_severity == Severity::Allow;
+	if !suid_files.is_empty() {
+		let reason = format!(
+			"package {} has SUID/SGID files or file capabilities: {}",
+			path_str,
+			suid_files.join(", ")
+		);
+		Some((noconfirm::EXIT_SUID_FILES, reason))
+	} else if has_install && !install_script_allowed {
+		let reason = format!(
+			"package {} has an .INSTALL script. Review it interactively, or allow it with --allow-install-script={}",
+			path_str, pkgname
+		);
+		Some((noconfirm::EXIT_INSTALL_SCRIPT, reason))
+	} else {
+		None
+	}
+}
+
 pub fn common_suffix_length(pkg_names: &[&str], archive_whitelist: &IndexSet<&str>) -> usize {
 	let min_len = pkg_names.iter().map(|p| p.len()).min().unwrap_or(0);
 	for suffix_length in 0..min_len {
@@ -189,8 +566,10 @@ pub fn common_suffix_length(pkg_names: &[&str], archive_whitelist: &IndexSet<&st
 
 #[cfg(test)]
 mod tests {
+	use crate::fake_wrappers::FakeAlpm;
 	use crate::tar_check::*;
 	use indexmap::IndexSet;
+	use std::str::FromStr;
 
 	fn test(files: &[&str], whitelist: &[&str], expected: usize) {
 		let set: IndexSet<&str> = whitelist.iter().copied().collect();
@@ -205,4 +584,72 @@ mod tests {
 		test(&["a-x-1.pkg.tar", "b-x-1.pkg.tar"], &["a-x"], 10);
 		test(&["a-x-1.pkg.tar", "b-x-1.pkg.tar"], &["a"], 12);
 	}
+
+	#[test]
+	fn test_file_conflicts() {
+		let alpm = FakeAlpm::from_fixture("alpm.json");
+		let files = [
+			"usr/bin/sh",
+			"usr/bin/up-to-date",
+			"usr/bin/tool",
+			"etc/foo.conf",
+			"usr/bin/foo",
+		];
+		let files = files.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+		let pkginfo = PackageInfo::parse("pkgname = up-to-date\nconflict = tool-git>=r1\n");
+		let srcinfo = Srcinfo::from_str(
+			"pkgbase = up-to-date\n\tpkgver = 3.0\n\tpkgrel = 1\n\npkgname = up-to-date\n\npkgname = up-to-date-docs\n",
+		)
+		.unwrap();
+		let replaceable = replaceable_owners(&pkginfo, Some(&srcinfo));
+		assert_eq!(
+			replaceable,
+			IndexSet::from(["up-to-date", "up-to-date-docs", "tool-git"].map(String::from))
+		);
+		let conflicts =
+			file_conflicts(&files, &replaceable, &alpm, |path| path != "usr/bin/foo").unwrap();
+		assert_eq!(
+			conflicts,
+			FileConflicts {
+				owned: vec![("usr/bin/sh".to_string(), "bash".to_string())],
+				unowned: vec!["etc/foo.conf".to_string()],
+			}
+		);
+	}
+
+	#[test]
+	fn test_noconfirm_rejection() {
+		let noconfirm = NoConfirm::new(&Some("allowed".to_string()));
+		let rules = TarCheckPolicy::default().rules("any");
+		let reject = |pkgname: &str, suid_files: &[&str], has_install: bool, rules: &Rules| {
+			let suid_files = suid_files.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+			noconfirm_rejection(
+				"a.pkg.tar",
+				pkgname,
+				&suid_files,
+				has_install,
+				&noconfirm,
+				rules,
+			)
+			.map(|(exit_code, _reason)| exit_code)
+		};
+		assert_eq!(reject("any", &[], false, &rules), None);
+		assert_eq!(reject("allowed", &[], true, &rules), None);
+		assert_eq!(
+			reject("any", &[], true, &rules),
+			Some(noconfirm::EXIT_INSTALL_SCRIPT)
+		);
+		assert_eq!(
+			reject("allowed", &["usr/bin/su"], true, &rules),
+			Some(noconfirm::EXIT_SUID_FILES)
+		);
+		let mut policy_rules = TarCheckPolicy::default().rules("any");
+		policy_rules.suid_allowlist = vec!["usr/bin/su".to_string()];
+		policy_rules.install_script_severity = Severity::Allow;
+		assert_eq!(reject("any", &["usr/bin/su"], true, &policy_rules), None);
+		assert_eq!(
+			reject("any", &["usr/bin/sudo"], false, &policy_rules),
+			Some(noconfirm::EXIT_SUID_FILES)
+		);
+	}
 }
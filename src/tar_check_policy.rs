@@ -0,0 +1,281 @@
+use colored::Colorize;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// What to do when a package breaks a policy rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+	/// The rule is not checked
+	Allow,
+	/// Report the violation, but let the user proceed
+	Warn,
+	/// Refuse to install the package
+	Block,
+}
+
+/// Rules of the `[default]` section, or overrides of a `[package.<pkgname>]` section
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PolicyRules {
+	forbidden_paths: Option<Vec<String>>,
+	forbidden_paths_severity: Option<Severity>,
+	suid_allowlist: Option<Vec<String>>,
+	suid_severity: Option<Severity>,
+	max_size_mb: Option<u64>,
+	max_size_severity: Option<Severity>,
+	install_script_severity: Option<Severity>,
+}
+
+/// Policy for built packages, read from `~/.config/rua/tar_check_policy.toml`.
+/// See `res/tar_check_policy.toml.example` for the format.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TarCheckPolicy {
+	default: PolicyRules,
+	package: HashMap<String, PolicyRules>,
+}
+
+/// Rules applying to one package, with the package overrides applied
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rules {
+	/// Paths without the leading slash, as they are stored in the archive
+	pub forbidden_paths: Vec<String>,
+	pub forbidden_paths_severity: Severity,
+	pub suid_allowlist: Vec<String>,
+	pub suid_severity: Severity,
+	pub max_size_mb: Option<u64>,
+	pub max_size_severity: Severity,
+	pub install_script_severity: Severity,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+	pub severity: Severity,
+	pub message: String,
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let severity = match self.severity {
+			Severity::Block => "BLOCK".bold().red(),
+			Severity::Warn => "WARN".bold().yellow(),
+			Severity::Allow => "ALLOW".normal(),
+		};
+		write!(f, "{} {}", severity, self.message)
+	}
+}
+
+const DEFAULT_FORBIDDEN_PATHS: [&str; 3] = [
+	"etc/sudoers.d",
+	"usr/lib/systemd/system",
+	"usr/share/libalpm/hooks",
+];
+
+impl TarCheckPolicy {
+	/// Reads the policy file. Without the file, the built-in defaults are used.
+	pub fn load(path: &Path) -> TarCheckPolicy {
+		if !path.exists() {
+			return TarCheckPolicy::default();
+		}
+		let content = fs::read_to_string(path)
+			.unwrap_or_else(|err| panic!("Failed to read tar_check policy {:?}, {}", path, err));
+		TarCheckPolicy::parse(&content).unwrap_or_else(|err| {
+			eprintln!("Failed to parse tar_check policy {:?}, {}", path, err);
+			std::process::exit(1)
+		})
+	}
+
+	fn parse(content: &str) -> Result<TarCheckPolicy, toml::de::Error> {
+		toml::from_str(content)
+	}
+
+	pub fn rules(&self, pkgname: &str) -> Rules {
+		let default = &self.default;
+		let package = self.package.get(pkgname);
+		macro_rules! rule {
+			($field:ident, $fallback:expr) => {
+				package
+					.and_then(|package| package.$field.clone())
+					.or_else(|| default.$field.clone())
+					.unwrap_or_else(|| $fallback)
+			};
+		}
+		let default_forbidden_paths = || DEFAULT_FORBIDDEN_PATHS.map(str::to_string).to_vec();
+		Rules {
+			forbidden_paths: normalize(rule!(forbidden_paths, default_forbidden_paths())),
+			forbidden_paths_severity: rule!(forbidden_paths_severity, Severity::Warn),
+			suid_allowlist: normalize(rule!(suid_allowlist, Vec::new())),
+			suid_severity: rule!(suid_severity, Severity::Warn),
+			max_size_mb: package
+				.and_then(|package| package.max_size_mb)
+				.or(default.max_size_mb),
+			max_size_severity: rule!(max_size_severity, Severity::Warn),
+			install_script_severity: rule!(install_script_severity, Severity::Warn),
+		}
+	}
+}
+
+fn normalize(paths: Vec<String>) -> Vec<String> {
+	paths
+		.iter()
+		.map(|path| path.trim_matches('/').to_string())
+		.collect()
+}
+
+fn is_under(path: &str, parent: &str) -> bool {
+	let path = path.trim_end_matches('/');
+	path == parent
+		|| path
+			.strip_prefix(parent)
+			.map_or(false, |rest| rest.starts_with('/'))
+}
+
+impl Rules {
+	pub fn allows_suid(&self, path: &str) -> bool {
+		self.suid_allowlist.iter().any(|allowed| allowed == path)
+	}
+
+	/// Checks the archive contents against the rules.
+	/// `total_size` is the sum of the entry sizes in bytes.
+	pub fn evaluate(
+		&self,
+		files: &[String],
+		suid_files: &[String],
+		total_size: u64,
+		has_install: bool,
+	) -> Vec<Violation> {
+		let mut result = Vec::new();
+		let mut violation = |severity: Severity, message: String| {
+			if severity != Severity::Allow {
+				result.push(Violation { severity, message });
+			}
+		};
+		for file in files {
+			if let Some(forbidden) = self.forbidden_paths.iter().find(|f| is_under(file, f)) {
+				violation(
+					self.forbidden_paths_severity,
+					format!("{} is under the forbidden path /{}", file, forbidden),
+				);
+			}
+		}
+		for file in suid_files.iter().filter(|file| !self.allows_suid(file)) {
+			violation(
+				self.suid_severity,
+				format!(
+					"{} is SUID/SGID or has capabilities, and is not in the SUID allowlist",
+					file
+				),
+			);
+		}
+		if let Some(max_size_mb) = self.max_size_mb {
+			if total_size > max_size_mb * 1024 * 1024 {
+				violation(
+					self.max_size_severity,
+					format!(
+						"package size {} MiB exceeds the maximum of {} MiB",
+						total_size / 1024 / 1024,
+						max_size_mb
+					),
+				);
+			}
+		}
+		if has_install {
+			violation(
+				self.install_script_severity,
+				"package has an .INSTALL script".to_string(),
+			);
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::tar_check_policy::*;
+
+	const POLICY: &str = r#"
+[default]
+forbidden_paths = ["/etc/sudoers.d", "/usr/share/libalpm/hooks/"]
+forbidden_paths_severity = "block"
+max_size_mb = 1
+install_script_severity = "block"
+
+[package.sudo]
+suid_allowlist = ["/usr/bin/sudo"]
+suid_severity = "block"
+forbidden_paths_severity = "allow"
+"#;
+
+	fn strings(values: &[&str]) -> Vec<String> {
+		values.iter().map(|v| v.to_string()).collect()
+	}
+
+	#[test]
+	fn test_rules() {
+		let policy = TarCheckPolicy::parse(POLICY).unwrap();
+		let sudo = policy.rules("sudo");
+		assert_eq!(
+			sudo.forbidden_paths,
+			["etc/sudoers.d", "usr/share/libalpm/hooks"]
+		);
+		assert_eq!(sudo.forbidden_paths_severity, Severity::Allow);
+		assert_eq!(sudo.suid_allowlist, ["usr/bin/sudo"]);
+		assert_eq!(sudo.max_size_mb, Some(1));
+		let builtin = TarCheckPolicy::default().rules("any");
+		assert_eq!(builtin.forbidden_paths.len(), 3);
+		assert_eq!(builtin.install_script_severity, Severity::Warn);
+		assert!(TarCheckPolicy::parse("[default]\nunknown = 1").is_err());
+	}
+
+	#[test]
+	fn test_evaluate() {
+		let policy = TarCheckPolicy::parse(POLICY).unwrap();
+		let files = strings(&["etc/sudoers.d/foo", "etc/sudoers.d.bak", "usr/bin/sudo"]);
+		let suid_files = strings(&["usr/bin/sudo", "usr/bin/other"]);
+		let severities = |pkgname: &str, total_size: u64, has_install: bool| {
+			policy
+				.rules(pkgname)
+				.evaluate(&files, &suid_files, total_size, has_install)
+				.into_iter()
+				.map(|violation| (violation.severity, violation.message))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(
+			severities("foo", 2 * 1024 * 1024, true),
+			[
+				(
+					Severity::Block,
+					"etc/sudoers.d/foo is under the forbidden path /etc/sudoers.d".to_string()
+				),
+				(
+					Severity::Warn,
+					"usr/bin/sudo is SUID/SGID or has capabilities, and is not in the SUID allowlist".to_string()
+				),
+				(
+					Severity::Warn,
+					"usr/bin/other is SUID/SGID or has capabilities, and is not in the SUID allowlist".to_string()
+				),
+				(
+					Severity::Warn,
+					"package size 2 MiB exceeds the maximum of 1 MiB".to_string()
+				),
+				(
+					Severity::Block,
+					"package has an .INSTALL script".to_string()
+				),
+			]
+		);
+		assert_eq!(
+			severities("sudo", 1024, false),
+			[(
+				Severity::Block,
+				"usr/bin/other is SUID/SGID or has capabilities, and is not in the SUID allowlist"
+					.to_string()
+			)]
+		);
+	}
+}
@@ -1,8 +1,12 @@
 // Commands that are run inside "bubblewrap" jail
 
+use crate::build_log::BuildLog;
 use crate::rua_paths;
 use crate::rua_paths::RuaPaths;
+use crate::srcinfo_diff;
 use crate::srcinfo_to_pkgbuild;
+use crate::terminal_util;
+use itertools::Itertools;
 use log::debug;
 use log::error;
 use log::info;
@@ -10,14 +14,19 @@ use log::trace;
 use srcinfo::Srcinfo;
 use std::fs;
 use std::fs::File;
+use std::io;
+use std::io::Read;
 use std::io::Write;
+use std::iter;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::process::ExitStatus;
 use std::process::Stdio;
 use std::str;
 use std::str::FromStr;
 use std::sync::Once;
+use std::thread;
 
 static BUBBLEWRAP_IS_RUNNABLE: Once = Once::new();
 /// Check if bubblewrap binary is runnable
@@ -59,7 +68,95 @@ fn jail_for_makepkg(rua_paths: &RuaPaths, cur_dir: &str, makepkg_dir: &str) -> C
 	command
 }
 
-fn download_srcinfo_sources(dir: &str, rua_paths: &RuaPaths) {
+/// Runs the command, saving its output to the log file.
+/// With `echo`, the output is also shown in the terminal as it comes.
+fn run_logged(command: &mut Command, log: &BuildLog, echo: bool) -> io::Result<ExitStatus> {
+	if !echo {
+		command
+			.stdout(log.file.try_clone()?)
+			.stderr(log.file.try_clone()?);
+		return command.status();
+	}
+	if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1 {
+		return in_pseudo_terminal(command, &log.path).status();
+	}
+	let log = &log.file;
+	command.stdout(Stdio::piped()).stderr(Stdio::piped());
+	let mut child = command.spawn()?;
+	let stdout = child
+		.stdout
+		.take()
+		.expect("Internal error: stdout is not piped");
+	let stderr = child
+		.stderr
+		.take()
+		.expect("Internal error: stderr is not piped");
+	thread::scope(|scope| {
+		scope.spawn(|| tee(stdout, io::stdout(), log));
+		scope.spawn(|| tee(stderr, io::stderr(), log));
+	});
+	child.wait()
+}
+
+/// Wraps the command into `script`, which runs it in a pseudo-terminal and appends its output
+/// to the log. Unlike with pipes, makepkg keeps its colors and can ask questions.
+fn in_pseudo_terminal(command: &Command, log_path: &Path) -> Command {
+	let shell_command = iter::once(command.get_program())
+		.chain(command.get_args())
+		.map(|arg| terminal_util::escape_bash_arg(&arg.to_string_lossy()))
+		.join(" ");
+	let mut script = Command::new("script");
+	script
+		.args(["--quiet", "--return", "--flush", "--append", "--command"])
+		.arg(shell_command)
+		.arg(log_path);
+	if let Some(dir) = command.get_current_dir() {
+		script.current_dir(dir);
+	}
+	for (key, value) in command.get_envs() {
+		match value {
+			Some(value) => script.env(key, value),
+			None => script.env_remove(key),
+		};
+	}
+	// `script` runs the command with $SHELL, which might not understand the quoting
+	script.env("SHELL", "/bin/sh");
+	script
+}
+
+fn tee(mut from: impl Read, mut terminal: impl Write, mut log: &File) {
+	let mut buffer = [0; 8192];
+	loop {
+		match from.read(&mut buffer) {
+			Ok(0) | Err(_) => break,
+			Ok(read) => {
+				terminal.write_all(&buffer[..read]).ok();
+				terminal.flush().ok();
+				log.write_all(&buffer[..read]).ok();
+			}
+		}
+	}
+}
+
+fn download_srcinfo_sources(
+	dir: &str,
+	rua_paths: &RuaPaths,
+	log: &BuildLog,
+	echo: bool,
+) -> Result<(), BuildFailure> {
+	let mismatches = srcinfo_mismatches(dir, rua_paths).map_err(|err| {
+		BuildFailure::other(format!(
+			"Cannot compare .SRCINFO with PKGBUILD in {}, {}",
+			dir, err
+		))
+	})?;
+	if !mismatches.is_empty() {
+		return Err(BuildFailure::other(format!(
+			"Refusing to download sources of {} using .SRCINFO, it does not match PKGBUILD:\n  {}",
+			dir,
+			mismatches.join("\n  ")
+		)));
+	}
 	let dir_path = PathBuf::from(dir).join("PKGBUILD.static");
 	let mut file = File::create(dir_path)
 		.unwrap_or_else(|err| panic!("Cannot create {}/PKGBUILD.static, {}", dir, err));
@@ -70,15 +167,32 @@ fn download_srcinfo_sources(dir: &str, rua_paths: &RuaPaths) {
 	file.write_all(srcinfo_to_pkgbuild::static_pkgbuild(&srcinfo_path).as_bytes())
 		.expect("cannot write to PKGBUILD.static");
 	info!("Downloading sources using .SRCINFO...");
-	let command = jail_for_makepkg(rua_paths, dir, dir)
+	let mut command = jail_for_makepkg(rua_paths, dir, dir);
+	command
 		.args(["--bind", dir, dir])
 		.args(["makepkg", "-f", "--verifysource"])
-		.args(["-p", "PKGBUILD.static"])
-		.status()
+		.args(["-p", "PKGBUILD.static"]);
+	let command = run_logged(&mut command, log, echo)
 		.unwrap_or_else(|e| panic!("Failed to fetch dependencies in directory {}, {}", dir, e));
-	assert!(command.success(), "Failed to download PKGBUILD sources");
+	if !command.success() {
+		return Err(BuildFailure::other(format!(
+			"Failed to download PKGBUILD sources in {}",
+			dir
+		)));
+	}
 	fs::remove_file(PathBuf::from(dir).join("PKGBUILD.static"))
 		.expect("Failed to clean up PKGBUILD.static");
+	Ok(())
+}
+
+/// Compares the committed .SRCINFO with the one generated from PKGBUILD,
+/// see `srcinfo_diff::mismatches`
+pub fn srcinfo_mismatches(dir: &str, rua_paths: &RuaPaths) -> Result<Vec<String>, String> {
+	let srcinfo_path = Path::new(dir).join(".SRCINFO");
+	let committed = Srcinfo::from_path(&srcinfo_path)
+		.map_err(|err| format!("failed to parse {:?}, {}", srcinfo_path, err))?;
+	let generated = generate_srcinfo(dir, rua_paths)?;
+	Ok(srcinfo_diff::mismatches(&committed, &generated))
 }
 
 pub fn generate_srcinfo(dir: &str, rua_paths: &RuaPaths) -> Result<Srcinfo, String> {
@@ -120,7 +234,31 @@ pub fn generate_srcinfo(dir: &str, rua_paths: &RuaPaths) -> Result<Srcinfo, Stri
 	Ok(srcinfo)
 }
 
-fn build_local(dir: &str, rua_paths: &RuaPaths, offline: bool, force: bool) {
+/// Why a package could not be built
+#[derive(Debug)]
+pub struct BuildFailure {
+	/// Exit code of makepkg, or 1 if it did not get to run
+	pub exit_code: i32,
+	pub message: String,
+}
+
+impl BuildFailure {
+	fn other(message: String) -> BuildFailure {
+		BuildFailure {
+			exit_code: 1,
+			message,
+		}
+	}
+}
+
+fn build_local(
+	dir: &str,
+	rua_paths: &RuaPaths,
+	offline: bool,
+	force: bool,
+	log: &BuildLog,
+	echo: bool,
+) -> Result<(), BuildFailure> {
 	debug!("{}:{} Building directory {}", file!(), line!(), dir);
 	let mut command = jail_for_makepkg(rua_paths, dir, dir);
 	if offline {
@@ -131,29 +269,62 @@ fn build_local(dir: &str, rua_paths: &RuaPaths, offline: bool, force: bool) {
 	if force {
 		command.arg("--force");
 	}
-	let command = command.status().unwrap_or_else(|e| {
+	let command = run_logged(&mut command, log, echo).unwrap_or_else(|e| {
 		panic!(
 			"Failed to execute ~/.config/rua/.system/security-wrapper.sh, {}",
 			e
 		)
 	});
-	if !command.success() {
-		eprintln!(
-			"Build failed with exit code {} in {}",
-			command
-				.code()
-				.map_or_else(|| "???".to_owned(), |c| c.to_string()),
-			dir,
-		);
-		std::process::exit(command.code().unwrap_or(1));
+	if command.success() {
+		Ok(())
+	} else {
+		Err(BuildFailure {
+			exit_code: command.code().unwrap_or(1),
+			message: format!(
+				"Build failed with exit code {} in {}",
+				command
+					.code()
+					.map_or_else(|| "???".to_owned(), |c| c.to_string()),
+				dir,
+			),
+		})
 	}
 }
 
-pub fn build_directory(dir: &str, rua_paths: &RuaPaths, offline: bool, force: bool) {
+pub fn build_directory(
+	dir: &str,
+	rua_paths: &RuaPaths,
+	offline: bool,
+	force: bool,
+	log: &BuildLog,
+) {
+	let result = try_build_directory(dir, rua_paths, offline, force, log, true);
+	if let Err(failure) = result {
+		eprintln!("{}", failure.message);
+		eprintln!("Build log is saved to {:?}", log.path);
+		std::process::exit(failure.exit_code);
+	}
+}
+
+/// Creates the log file for building a package, named after its pkgbase and version
+pub fn new_build_log(pkgbase: &str, version: &str, rua_paths: &RuaPaths) -> BuildLog {
+	BuildLog::create(&rua_paths.logs_dir(pkgbase), version)
+}
+
+/// Builds the directory like `build_directory` does, but reports failures instead of exiting.
+/// The output is saved to `log`, and with `echo` it is also shown in the terminal.
+pub fn try_build_directory(
+	dir: &str,
+	rua_paths: &RuaPaths,
+	offline: bool,
+	force: bool,
+	log: &BuildLog,
+	echo: bool,
+) -> Result<(), BuildFailure> {
 	if offline {
-		download_srcinfo_sources(dir, rua_paths);
+		download_srcinfo_sources(dir, rua_paths, log, echo)?;
 	}
-	build_local(dir, rua_paths, offline, force);
+	build_local(dir, rua_paths, offline, force, log, echo)
 }
 
 /// Perform a shellcheck check of a PKGBUILD, taking care of special variables